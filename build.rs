@@ -0,0 +1,442 @@
+//Turns keymap.toml into the keymap tables of src/keyboard_layouts.rs, see the comment at the
+//top of keymap.toml for the key names
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+#[allow(dead_code)]
+#[path = "src/eeprom_map.rs"]
+mod eeprom_map;
+
+//the combos keep a bit per key in a u128, as many keys as the key buffer holds
+const MAX_KEYS: usize = 128;
+//the key buffer has 2 bits for 128 keys, shared by both halves of a split board
+const HALVES: usize = 2;
+const MAX_MATRIX_KEYS: usize = 128 / HALVES;
+//layers are a bit each in a u8
+const MAX_LAYERS: usize = 8;
+//the keymap region of the EEPROM minus the blob header, 4 bytes per action
+const MAX_STORED_ACTIONS: usize = (eeprom_map::KEYMAP_SIZE - eeprom_map::BLOB_HEADER_LEN) / 4;
+
+#[rustfmt::skip]
+const KEYCODES: &[(&str, &str)] = &[
+    ("KC_A", "A"), ("KC_B", "B"), ("KC_C", "C"), ("KC_D", "D"), ("KC_E", "E"), ("KC_F", "F"),
+    ("KC_G", "G"), ("KC_H", "H"), ("KC_I", "I"), ("KC_J", "J"), ("KC_K", "K"), ("KC_L", "L"),
+    ("KC_M", "M"), ("KC_N", "N"), ("KC_O", "O"), ("KC_P", "P"), ("KC_Q", "Q"), ("KC_R", "R"),
+    ("KC_S", "S"), ("KC_T", "T"), ("KC_U", "U"), ("KC_V", "V"), ("KC_W", "W"), ("KC_X", "X"),
+    ("KC_Y", "Y"), ("KC_Z", "Z"),
+    ("KC_1", "N1"), ("KC_2", "N2"), ("KC_3", "N3"), ("KC_4", "N4"), ("KC_5", "N5"),
+    ("KC_6", "N6"), ("KC_7", "N7"), ("KC_8", "N8"), ("KC_9", "N9"), ("KC_0", "N0"),
+    ("KC_ENT", "Enter"), ("KC_ENTER", "Enter"), ("KC_ESC", "Escape"), ("KC_ESCAPE", "Escape"),
+    ("KC_BSPC", "Backspace"), ("KC_TAB", "Tab"), ("KC_SPC", "Space"), ("KC_SPACE", "Space"),
+    ("KC_MINS", "Minus"), ("KC_EQL", "Equal"), ("KC_LBRC", "LeftBracket"),
+    ("KC_RBRC", "RightBracket"), ("KC_BSLS", "Backslash"), ("KC_NUHS", "NonUsHash"),
+    ("KC_SCLN", "Semicolon"), ("KC_QUOT", "Quote"), ("KC_GRV", "Grave"), ("KC_COMM", "Comma"),
+    ("KC_DOT", "Dot"), ("KC_SLSH", "Slash"), ("KC_CAPS", "CapsLock"),
+    ("KC_F1", "F1"), ("KC_F2", "F2"), ("KC_F3", "F3"), ("KC_F4", "F4"), ("KC_F5", "F5"),
+    ("KC_F6", "F6"), ("KC_F7", "F7"), ("KC_F8", "F8"), ("KC_F9", "F9"), ("KC_F10", "F10"),
+    ("KC_F11", "F11"), ("KC_F12", "F12"),
+    ("KC_PSCR", "PrintScreen"), ("KC_SCRL", "ScrollLock"), ("KC_PAUS", "Pause"),
+    ("KC_INS", "Insert"), ("KC_HOME", "Home"), ("KC_PGUP", "PageUp"), ("KC_DEL", "Delete"),
+    ("KC_END", "End"), ("KC_PGDN", "PageDown"), ("KC_RGHT", "Right"), ("KC_RIGHT", "Right"),
+    ("KC_LEFT", "Left"), ("KC_DOWN", "Down"), ("KC_UP", "Up"),
+    ("KC_NUM", "NumLock"), ("KC_PSLS", "KpSlash"), ("KC_PAST", "KpAsterisk"),
+    ("KC_PMNS", "KpMinus"), ("KC_PPLS", "KpPlus"), ("KC_PENT", "KpEnter"),
+    ("KC_P1", "Kp1"), ("KC_P2", "Kp2"), ("KC_P3", "Kp3"), ("KC_P4", "Kp4"), ("KC_P5", "Kp5"),
+    ("KC_P6", "Kp6"), ("KC_P7", "Kp7"), ("KC_P8", "Kp8"), ("KC_P9", "Kp9"), ("KC_P0", "Kp0"),
+    ("KC_PDOT", "KpDot"), ("KC_NUBS", "NonUsBackslash"), ("KC_APP", "Application"),
+    ("KC_LCTL", "LCtrl"), ("KC_LSFT", "LShift"), ("KC_LALT", "LAlt"), ("KC_LGUI", "LGui"),
+    ("KC_RCTL", "RCtrl"), ("KC_RSFT", "RShift"), ("KC_RALT", "RAlt"), ("KC_RGUI", "RGui"),
+];
+//name, keycode and bit in Modifiers
+#[rustfmt::skip]
+const MODIFIERS: &[(&str, &str, u8)] = &[
+    ("MOD_LCTL", "LCtrl", 0), ("MOD_LSFT", "LShift", 1), ("MOD_LALT", "LAlt", 2),
+    ("MOD_LGUI", "LGui", 3), ("MOD_RCTL", "RCtrl", 4), ("MOD_RSFT", "RShift", 5),
+    ("MOD_RALT", "RAlt", 6), ("MOD_RGUI", "RGui", 7),
+];
+
+struct Keymap {
+    layer_count: usize,
+    //tap-hold entries in the order they first show up, LT and MT keys are indices into them
+    tap_holds: Vec<String>,
+    //TD and M numbers, TAP_DANCES and MACROS are written by hand in keyboard_layouts.rs, the
+    //generated code checks them against their lengths
+    tap_dances: BTreeSet<u8>,
+    macros: BTreeSet<u8>,
+}
+
+fn main() {
+    let manifest = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let path = env::var("KEYMAP").map_or_else(|_| manifest.join("keymap.toml"), PathBuf::from);
+    println!("cargo:rerun-if-changed={}", path.display());
+    println!("cargo:rerun-if-env-changed=KEYMAP");
+    //next to this file, tools/firmware_tests runs it from its own directory
+    let eeprom_map = Path::new(file!()).with_file_name("src/eeprom_map.rs");
+    println!("cargo:rerun-if-changed={}", eeprom_map.display());
+    match generate(&path) {
+        Ok(code) => {
+            let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("keymap.rs");
+            fs::write(out, code).unwrap();
+        }
+        Err(error) => {
+            eprintln!("{}: {}", path.display(), error);
+            process::exit(1);
+        }
+    }
+}
+
+fn generate(path: &Path) -> Result<String, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let file: toml::Value = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+    let layout = file.get("layout").ok_or("no [layout]")?;
+    let size = |name: &str| {
+        layout
+            .get(name)
+            .and_then(|size| size.as_integer())
+            .filter(|size| (1..=MAX_MATRIX_KEYS as i64).contains(size))
+            .map(|size| size as usize)
+            .ok_or_else(|| format!("[layout] needs {} between 1 and {}", name, MAX_MATRIX_KEYS))
+    };
+    let (rows, cols) = (size("rows")?, size("cols")?);
+    if rows * cols > MAX_MATRIX_KEYS {
+        return Err(format!(
+            "a {}x{} matrix doesn't fit into the key buffer, {} keys per half at most",
+            rows, cols, MAX_MATRIX_KEYS
+        ));
+    }
+    let list = |name: &str, len: usize| {
+        layout
+            .get(name)
+            .and_then(|list| list.as_array())
+            .ok_or_else(|| format!("[layout] needs a {} list", name))?
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                entry
+                    .as_array()
+                    .filter(|entry| entry.len() == len)
+                    .ok_or_else(|| format!("{} entry {} needs {} numbers", name, i, len))
+            })
+            .collect::<Result<Vec<_>, String>>()
+    };
+    let matrix = list("matrix", 3)?;
+    let key_count = matrix.len();
+    if key_count == 0 || key_count > MAX_KEYS {
+        return Err(format!(
+            "the layout has {} keys, 1 to {} are possible",
+            key_count, MAX_KEYS
+        ));
+    }
+    let position = list("position", 2)?;
+    if position.len() != key_count {
+        return Err(format!(
+            "the layout has {} matrix entries and {} positions",
+            key_count,
+            position.len()
+        ));
+    }
+    let number = |value: &toml::Value| {
+        value
+            .as_integer()
+            .map(|value| value as f64)
+            .or_else(|| value.as_float())
+    };
+    //scan index of every key, the halves come one after the other and row after row
+    let mut scanned = Vec::new();
+    let mut physical = Vec::new();
+    for (key, (at, place)) in matrix.iter().zip(&position).enumerate() {
+        let at: Vec<_> = at.iter().filter_map(|value| value.as_integer()).collect();
+        let (half, row, col) = match at[..] {
+            [half, row, col]
+                if (0..HALVES as i64).contains(&half)
+                    && (0..rows as i64).contains(&row)
+                    && (0..cols as i64).contains(&col) =>
+            {
+                (half as usize, row as usize, col as usize)
+            }
+            _ => {
+                return Err(format!(
+                    "matrix entry {} needs a half below {}, a row below {} and a column below {}",
+                    key, HALVES, rows, cols
+                ))
+            }
+        };
+        let index = (half * rows + row) * cols + col;
+        if scanned.contains(&index) {
+            return Err(format!("matrix entry {:?} is listed twice", at));
+        }
+        scanned.push(index);
+        //in quarter key widths
+        let place: Vec<_> = place
+            .iter()
+            .filter_map(number)
+            .map(|value| (value * 4.0).round())
+            .collect();
+        let (x, y) = match place[..] {
+            [x, y] if (0.0..=255.0).contains(&x) && (0.0..=255.0).contains(&y) => {
+                (x as u8, y as u8)
+            }
+            _ => return Err(format!("position {} has to be 0 to 63.75 key widths", key)),
+        };
+        physical.push((half, row, col, x, y));
+    }
+    let layers = file
+        .get("layers")
+        .and_then(|layers| layers.as_array())
+        .ok_or("no [[layers]]")?;
+    if layers.is_empty() || layers.len() > MAX_LAYERS {
+        return Err(format!(
+            "{} layers, 1 to {} are possible",
+            layers.len(),
+            MAX_LAYERS
+        ));
+    }
+    if layers.len() * key_count > MAX_STORED_ACTIONS {
+        return Err(format!(
+            "{} layers of {} keys don't fit into the EEPROM, {} keys at most",
+            layers.len(),
+            key_count,
+            MAX_STORED_ACTIONS
+        ));
+    }
+    let mut keymap = Keymap {
+        layer_count: layers.len(),
+        tap_holds: Vec::new(),
+        tap_dances: BTreeSet::new(),
+        macros: BTreeSet::new(),
+    };
+    let mut code = String::new();
+    writeln!(code, "//generated by build.rs from {}", path.display()).unwrap();
+    writeln!(code, "pub const KEY_COUNT: usize = {};", key_count).unwrap();
+    writeln!(code, "pub const LAYER_COUNT: usize = {};", layers.len()).unwrap();
+    writeln!(code, "pub const MATRIX_ROWS: usize = {};", rows).unwrap();
+    writeln!(code, "pub const MATRIX_COLS: usize = {};", cols).unwrap();
+    writeln!(code, "#[rustfmt::skip]").unwrap();
+    writeln!(code, "pub const LAYOUT: [PhysicalKey; KEY_COUNT] = [").unwrap();
+    for (half, row, col, x, y) in &physical {
+        writeln!(
+            code,
+            "    PhysicalKey {{ half: {}, row: {}, col: {}, x: {}, y: {} }},",
+            half, row, col, x, y
+        )
+        .unwrap();
+    }
+    writeln!(code, "];").unwrap();
+    writeln!(code, "#[rustfmt::skip]").unwrap();
+    writeln!(
+        code,
+        "pub const KEYMAP: [[Action; KEY_COUNT]; LAYER_COUNT] = ["
+    )
+    .unwrap();
+    for (i, layer) in layers.iter().enumerate() {
+        let name = layer
+            .get("name")
+            .and_then(|name| name.as_str())
+            .map_or_else(
+                || format!("layer {}", i),
+                |name| format!("layer {} ({})", i, name),
+            );
+        let keys = layer
+            .get("keys")
+            .and_then(|keys| keys.as_array())
+            .ok_or_else(|| format!("{} needs a keys list", name))?;
+        if keys.len() != key_count {
+            return Err(format!(
+                "{} has {} keys, the layout has {}",
+                name,
+                keys.len(),
+                key_count
+            ));
+        }
+        let mut actions = Vec::new();
+        for (position, key) in keys.iter().enumerate() {
+            let key = key
+                .as_str()
+                .ok_or_else(|| format!("{} key {}: has to be a string", name, position))?;
+            actions.push(
+                keymap
+                    .action(key)
+                    .map_err(|e| format!("{} key {}: {}", name, position, e))?,
+            );
+        }
+        writeln!(code, "    [").unwrap();
+        for action in &actions {
+            writeln!(code, "        {},", action).unwrap();
+        }
+        writeln!(code, "    ],").unwrap();
+    }
+    writeln!(code, "];").unwrap();
+    writeln!(code, "#[rustfmt::skip]").unwrap();
+    writeln!(
+        code,
+        "pub const TAP_HOLDS: [TapHold; {}] = [",
+        keymap.tap_holds.len()
+    )
+    .unwrap();
+    for tap_hold in &keymap.tap_holds {
+        writeln!(code, "    {},", tap_hold).unwrap();
+    }
+    writeln!(code, "];").unwrap();
+    for (name, table, ids) in [
+        ("TD", "TAP_DANCES", &keymap.tap_dances),
+        ("M", "MACROS", &keymap.macros),
+    ] {
+        for id in ids {
+            let message = format!("{}({}) of the keymap has no entry in {}", name, id, table);
+            let check = match id {
+                0 => format!("!{}.is_empty()", table),
+                _ => format!("{} < {}.len()", id, table),
+            };
+            writeln!(code, "const _: () = assert!({}, {:?});", check, message).unwrap();
+        }
+    }
+    Ok(code)
+}
+
+impl Keymap {
+    fn action(&mut self, key: &str) -> Result<String, String> {
+        let key = key.trim();
+        let (name, args) = match key.find('(') {
+            Some(open) if key.ends_with(')') => (&key[..open], Some(&key[open + 1..key.len() - 1])),
+            Some(_) => return Err(format!("`{}` is missing the closing parenthesis", key)),
+            None => (key, None),
+        };
+        let args: Vec<&str> = match (name, args) {
+            //the character may be a comma
+            ("UC", Some(arg)) => vec![arg],
+            (_, Some(args)) => args.split(',').map(str::trim).collect(),
+            (_, None) => Vec::new(),
+        };
+        let count = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(format!("`{}` takes {} argument(s)", name, n))
+            }
+        };
+        Ok(match name {
+            "_______" | "KC_TRNS" => {
+                count(0)?;
+                "Action::Transparent".to_string()
+            }
+            "XXXXXXX" | "KC_NO" => {
+                count(0)?;
+                "Action::No".to_string()
+            }
+            "MO" => {
+                count(1)?;
+                format!("Action::Layer({})", self.layer(args[0])?)
+            }
+            "OSL" => {
+                count(1)?;
+                format!("Action::OneShotLayer({})", self.layer(args[0])?)
+            }
+            "NW" => {
+                count(1)?;
+                format!("Action::NumWord({})", self.layer(args[0])?)
+            }
+            "LT" => {
+                count(2)?;
+                let hold = format!("Hold::Layer({})", self.layer(args[0])?);
+                self.tap_hold(keycode(args[1])?, hold, "LAYER_TAP")?
+            }
+            "MT" => {
+                count(2)?;
+                let modifier = MODIFIERS
+                    .iter()
+                    .find(|(name, _, _)| *name == args[0])
+                    .ok_or_else(|| format!("unknown modifier `{}`", args[0]))?;
+                let hold = format!("Hold::Modifier(KeyCode::{})", modifier.1);
+                self.tap_hold(keycode(args[1])?, hold, "HOME_ROW_MOD")?
+            }
+            "TD" => {
+                count(1)?;
+                let id = number(args[0])?;
+                self.tap_dances.insert(id);
+                format!("Action::TapDance({})", id)
+            }
+            "M" => {
+                count(1)?;
+                let id = number(args[0])?;
+                self.macros.insert(id);
+                format!("Action::Macro({})", id)
+            }
+            "UC" => {
+                let mut chars = args[0].chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => format!("Action::Unicode({:?})", c),
+                    _ => return Err(format!("`{}` needs exactly one character", key)),
+                }
+            }
+            "OSM" => {
+                count(1)?;
+                let mut bits = 0u8;
+                for modifier in args[0].split('|').map(str::trim) {
+                    let (_, _, bit) = MODIFIERS
+                        .iter()
+                        .find(|(name, _, _)| *name == modifier)
+                        .ok_or_else(|| format!("unknown modifier `{}`", modifier))?;
+                    bits |= 1 << bit;
+                }
+                format!("Action::OneShotModifiers(Modifiers({:#04x}))", bits)
+            }
+            "QK_LEAD" => {
+                count(0)?;
+                "Action::Leader".to_string()
+            }
+            "CW_TOGG" => {
+                count(0)?;
+                "Action::CapsWord".to_string()
+            }
+            "MENU" => {
+                count(0)?;
+                "Action::Menu".to_string()
+            }
+            _ if args.is_empty() => format!("Action::Key(KeyCode::{})", keycode(name)?),
+            _ => return Err(format!("unknown key `{}`", key)),
+        })
+    }
+    fn layer(&self, arg: &str) -> Result<u8, String> {
+        let layer = number(arg)?;
+        if (layer as usize) < self.layer_count {
+            Ok(layer)
+        } else {
+            Err(format!(
+                "layer {} doesn't exist, there are {}",
+                layer, self.layer_count
+            ))
+        }
+    }
+    fn tap_hold(&mut self, tap: &str, hold: String, config: &str) -> Result<String, String> {
+        let entry = format!(
+            "TapHold {{ tap: KeyCode::{}, hold: {}, config: {} }}",
+            tap, hold, config
+        );
+        let id = match self.tap_holds.iter().position(|t| *t == entry) {
+            Some(id) => id,
+            None if self.tap_holds.len() <= u8::MAX as usize => {
+                self.tap_holds.push(entry);
+                self.tap_holds.len() - 1
+            }
+            None => return Err("more than 256 different LT and MT keys".to_string()),
+        };
+        Ok(format!("Action::TapHold({})", id))
+    }
+}
+
+fn keycode(name: &str) -> Result<&'static str, String> {
+    KEYCODES
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, code)| *code)
+        .ok_or_else(|| format!("unknown keycode `{}`", name))
+}
+
+fn number(arg: &str) -> Result<u8, String> {
+    arg.parse()
+        .map_err(|_| format!("`{}` has to be a number from 0 to 255", arg))
+}
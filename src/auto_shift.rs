@@ -0,0 +1,122 @@
+use crate::keycodes::KeyCode;
+use crate::ps2::Typematic;
+
+#[derive(Clone, Copy)]
+pub struct AutoShiftConfig {
+    //ms a key has to be held to come out shifted
+    pub timeout: u16,
+    pub letters: bool,
+    pub numbers: bool,
+    //the punctuation keys between minus and slash and the ISO key
+    pub symbols: bool,
+    //a shifted key held past the typematic delay repeats
+    pub repeat: bool,
+}
+
+impl AutoShiftConfig {
+    pub fn applies(&self, code: KeyCode) -> bool {
+        let id = code as u8;
+        (self.letters && (KeyCode::A as u8..=KeyCode::Z as u8).contains(&id))
+            || (self.numbers && (KeyCode::N1 as u8..=KeyCode::N0 as u8).contains(&id))
+            || (self.symbols
+                && ((KeyCode::Minus as u8..=KeyCode::Slash as u8).contains(&id)
+                    || code == KeyCode::NonUsBackslash))
+    }
+}
+
+pub enum AutoShiftOutput {
+    //press and release right away, shifted or not
+    Tap(KeyCode, bool),
+    //shifted press that stays down until the key is released
+    Press(KeyCode),
+    //typematic make code of the held key
+    Repeat(KeyCode),
+    Release(KeyCode),
+}
+
+enum State {
+    Idle,
+    //key, code and press time, waiting for the release or the timeout
+    Pending(u8, KeyCode, u32),
+    //key and code pressed shifted, if it repeats the time of the last make code and the ms
+    //until the next one
+    Shifted(u8, KeyCode, Option<(u32, u16)>),
+}
+
+//Holds back the press of a key until it is either released, then it gets tapped as it is,
+//or held for the timeout, then it gets pressed with shift. Only one key at a time, the press
+//of another key taps the waiting one first.
+pub struct AutoShift {
+    state: State,
+}
+
+impl AutoShift {
+    pub fn new() -> Self {
+        Self { state: State::Idle }
+    }
+    //another key got pressed, returns the waiting key which has to be tapped unshifted
+    pub fn interrupt(&mut self) -> Option<KeyCode> {
+        match self.state {
+            State::Pending(_, code, _) => {
+                self.state = State::Idle;
+                Some(code)
+            }
+            //only the last key pressed repeats
+            State::Shifted(key, code, Some(_)) => {
+                self.state = State::Shifted(key, code, None);
+                None
+            }
+            _ => None,
+        }
+    }
+    pub fn press(&mut self, key: u8, code: KeyCode, time: u32) {
+        self.state = State::Pending(key, code, time);
+    }
+    //None if the key isn't the one auto-shift took
+    pub fn release(
+        &mut self,
+        key: u8,
+        time: u32,
+        config: &AutoShiftConfig,
+    ) -> Option<AutoShiftOutput> {
+        match self.state {
+            State::Pending(pending, code, since) if pending == key => {
+                self.state = State::Idle;
+                //the press may have been decided late, the time of the release counts
+                Some(AutoShiftOutput::Tap(
+                    code,
+                    time.wrapping_sub(since) >= config.timeout as u32,
+                ))
+            }
+            State::Shifted(shifted, code, _) if shifted == key => {
+                self.state = State::Idle;
+                Some(AutoShiftOutput::Release(code))
+            }
+            _ => None,
+        }
+    }
+    pub fn poll(
+        &mut self,
+        now: u32,
+        config: &AutoShiftConfig,
+        typematic: Typematic,
+    ) -> Option<AutoShiftOutput> {
+        match self.state {
+            State::Pending(key, code, since)
+                if now.wrapping_sub(since) >= config.timeout as u32 =>
+            {
+                let repeat = config.repeat.then_some((now, typematic.delay));
+                self.state = State::Shifted(key, code, repeat);
+                Some(AutoShiftOutput::Press(code))
+            }
+            State::Shifted(key, code, Some((last, wait)))
+                if now.wrapping_sub(last) >= wait as u32 =>
+            {
+                let repeat = (last.wrapping_add(wait as u32), typematic.period);
+                self.state = State::Shifted(key, code, Some(repeat));
+                Some(AutoShiftOutput::Repeat(code))
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use crate::keyboard_layouts::{Action, KEY_COUNT};
+use heapless::Vec;
+
+const OUTPUT_LEN: usize = 16;
+//held back events, combo key presses and the releases of other keys in between
+const MAX_PENDING: usize = 8;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ComboRelease {
+    //the combo action stays pressed until every key of it is released
+    AllReleased,
+    FirstReleased,
+}
+
+#[derive(Clone, Copy)]
+pub struct Combo {
+    //bit per matrix key, see keys()
+    pub keys: u128,
+    pub action: Action,
+    //bit per layer the combo works on
+    pub layers: u8,
+    pub release: ComboRelease,
+    //ms between the first and the last key press
+    pub term: u16,
+}
+
+pub const fn keys(indices: &[usize]) -> u128 {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < indices.len() {
+        mask |= 1 << indices[i];
+        i += 1;
+    }
+    mask
+}
+
+struct ActiveCombo {
+    id: u8,
+    //keys of the combo that are still down
+    held: u128,
+    released: bool,
+}
+
+//Turns presses of combo keys that happen within the term into a press of the virtual key
+//KEY_COUNT + combo id. Presses that might still become a combo are held back, if the
+//combo doesn't complete they come out unchanged and in order. The key state is kept as
+//bitmasks, a combo costs no RAM beyond its entry in the const table.
+pub struct ComboDetector {
+    pending: Vec<(u8, bool, u32), MAX_PENDING>,
+    //keys of the held back presses
+    pending_keys: u128,
+    active: Vec<ActiveCombo, 4>,
+    output: Vec<(u8, bool, u32), OUTPUT_LEN>,
+}
+
+impl ComboDetector {
+    pub fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            pending_keys: 0,
+            active: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+    //returns false if there is no room for the event, it has to be fed again later
+    pub fn event(
+        &mut self,
+        combos: &[Combo],
+        layers: u8,
+        key: u8,
+        pressed: bool,
+        time: u32,
+    ) -> bool {
+        if self.output.capacity() - self.output.len() < self.pending.len() + 2 {
+            return false;
+        }
+        let bit = 1u128 << key;
+        if !pressed {
+            if self.pending_keys & bit != 0 {
+                self.flush(combos, layers);
+            }
+            if let Some(i) = self.active.iter().position(|c| c.held & bit != 0) {
+                let combo = &mut self.active[i];
+                combo.held &= !bit;
+                let release = match combos[combo.id as usize].release {
+                    ComboRelease::AllReleased => combo.held == 0,
+                    ComboRelease::FirstReleased => true,
+                };
+                if release && !combo.released {
+                    combo.released = true;
+                    let _ = self.output.push((KEY_COUNT as u8 + combo.id, false, time));
+                }
+                if combo.held == 0 {
+                    self.active.swap_remove(i);
+                }
+                return true;
+            }
+            if self.pending_keys == 0 {
+                let _ = self.output.push((key, false, time));
+            } else {
+                //stays behind the held back presses that happened before it
+                let _ = self.pending.push((key, false, time));
+                if self.pending.is_full() {
+                    self.flush(combos, layers);
+                }
+            }
+            return true;
+        }
+        if self
+            .candidates(combos, layers, self.pending_keys | bit)
+            .next()
+            .is_none()
+        {
+            self.flush(combos, layers);
+            if self.candidates(combos, layers, bit).next().is_none() {
+                let _ = self.output.push((key, true, time));
+                return true;
+            }
+        }
+        let _ = self.pending.push((key, true, time));
+        self.pending_keys |= bit;
+        //fire right away unless a bigger combo could still follow
+        if self
+            .candidates(combos, layers, self.pending_keys)
+            .all(|(_, combo)| combo.keys == self.pending_keys)
+            || self.pending.is_full()
+        {
+            self.flush(combos, layers);
+        }
+        true
+    }
+    //resolves pending keys once the term of every combo they could be part of ran out
+    pub fn poll(&mut self, combos: &[Combo], layers: u8, now: u32) {
+        if let Some((_, _, first)) = self.pending.first() {
+            let term = self
+                .candidates(combos, layers, self.pending_keys)
+                .map(|(_, combo)| combo.term)
+                .max()
+                .unwrap_or(0);
+            if now.wrapping_sub(*first) >= term as u32 {
+                self.flush(combos, layers);
+            }
+        }
+    }
+    pub fn next_output(&self) -> Option<(u8, bool, u32)> {
+        self.output.first().copied()
+    }
+    pub fn commit(&mut self) {
+        if !self.output.is_empty() {
+            self.output.remove(0);
+        }
+    }
+    fn candidates<'a>(
+        &self,
+        combos: &'a [Combo],
+        layers: u8,
+        keys: u128,
+    ) -> impl Iterator<Item = (usize, &'a Combo)> {
+        combos
+            .iter()
+            .enumerate()
+            .filter(move |(_, combo)| combo.layers & layers != 0 && combo.keys & keys == keys)
+    }
+    //sends the pending keys as the combo they form, or as they are if they form none
+    fn flush(&mut self, combos: &[Combo], layers: u8) {
+        let keys = self.pending_keys;
+        match self
+            .candidates(combos, layers, keys)
+            .find(|(_, combo)| combo.keys == keys)
+        {
+            Some((id, _)) if self.active.len() < self.active.capacity() => {
+                let mut time = 0;
+                for (key, pressed, event_time) in &self.pending {
+                    if *pressed {
+                        time = *event_time;
+                    } else {
+                        let _ = self.output.push((*key, false, *event_time));
+                    }
+                }
+                let _ = self.output.push((KEY_COUNT as u8 + id as u8, true, time));
+                let _ = self.active.push(ActiveCombo {
+                    id: id as u8,
+                    held: keys,
+                    released: false,
+                });
+            }
+            _ => {
+                self.output.extend(self.pending.iter().copied());
+            }
+        }
+        self.pending.clear();
+        self.pending_keys = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keycodes::KeyCode;
+
+    //keys of the left half of a split board come after the 49 of the right one
+    const COMBOS: [Combo; 1] = [Combo {
+        keys: keys(&[60, 97]),
+        action: Action::Key(KeyCode::Escape),
+        layers: 0b01,
+        release: ComboRelease::AllReleased,
+        term: 50,
+    }];
+
+    fn outputs(detector: &mut ComboDetector) -> Vec<(u8, bool, u32), OUTPUT_LEN> {
+        let mut outputs = Vec::new();
+        while let Some(output) = detector.next_output() {
+            outputs.push(output).unwrap();
+            detector.commit();
+        }
+        outputs
+    }
+
+    #[test]
+    fn keys_of_the_left_half() {
+        let combo = KEY_COUNT as u8;
+        let mut detector = ComboDetector::new();
+        assert!(detector.event(&COMBOS, 0b01, 97, true, 0));
+        assert!(outputs(&mut detector).is_empty());
+        assert!(detector.event(&COMBOS, 0b01, 60, true, 10));
+        assert!(detector.event(&COMBOS, 0b01, 97, false, 30));
+        assert!(detector.event(&COMBOS, 0b01, 60, false, 40));
+        assert_eq!(
+            outputs(&mut detector),
+            [(combo, true, 10), (combo, false, 40)]
+        );
+
+        //one key alone comes out after the term
+        assert!(detector.event(&COMBOS, 0b01, 97, true, 100));
+        detector.poll(&COMBOS, 0b01, 149);
+        assert!(outputs(&mut detector).is_empty());
+        detector.poll(&COMBOS, 0b01, 150);
+        assert!(detector.event(&COMBOS, 0b01, 97, false, 160));
+        assert_eq!(outputs(&mut detector), [(97, true, 100), (97, false, 160)]);
+    }
+}
@@ -0,0 +1,78 @@
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use heapless::Vec;
+
+pub use crate::eeprom_map::{
+    BLOB_HEADER_LEN, EXPANSION_ADDRESS, EXPANSION_SIZE, KEYMAP_ADDRESS, KEYMAP_SIZE, MACRO_ADDRESS,
+    MACRO_SIZE, SETTINGS_ADDRESS, SETTINGS_SIZE,
+};
+
+//24LC256 style I2C EEPROM with 16 bit addressing
+pub const PAGE_SIZE: usize = 64;
+
+//write cycle takes up to 5ms, the chip doesn't ack while it is busy
+const WRITE_POLL_RETRIES: u32 = 10_000;
+
+pub struct Eeprom<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C, E> Eeprom<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+    pub fn read(&mut self, address: u16, buffer: &mut [u8]) -> Result<(), E> {
+        self.i2c
+            .write_read(self.address, &address.to_be_bytes(), buffer)
+    }
+    pub fn write(&mut self, mut address: u16, mut data: &[u8]) -> Result<(), E> {
+        while !data.is_empty() {
+            //page writes wrap around at the page boundary
+            let len = data.len().min(PAGE_SIZE - address as usize % PAGE_SIZE);
+            let mut frame = [0u8; PAGE_SIZE + 2];
+            frame[..2].copy_from_slice(&address.to_be_bytes());
+            frame[2..len + 2].copy_from_slice(&data[..len]);
+            self.i2c.write(self.address, &frame[..len + 2])?;
+            self.wait_for_write(address)?;
+            address += len as u16;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+    //Reads data written by write_blob, leaves the buffer empty if there is none
+    pub fn read_blob<const N: usize>(
+        &mut self,
+        address: u16,
+        magic: [u8; 2],
+        data: &mut Vec<u8, N>,
+    ) -> Result<(), E> {
+        let mut header = [0u8; BLOB_HEADER_LEN];
+        self.read(address, &mut header)?;
+        let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+        data.clear();
+        //an erased EEPROM reads as 0xFF and has no valid header
+        if header[..2] != magic || len > N {
+            return Ok(());
+        }
+        let _ = data.resize(len, 0);
+        self.read(address + BLOB_HEADER_LEN as u16, data)
+    }
+    pub fn write_blob(&mut self, address: u16, magic: [u8; 2], data: &[u8]) -> Result<(), E> {
+        let len = (data.len() as u16).to_le_bytes();
+        self.write(address, &[magic[0], magic[1], len[0], len[1]])?;
+        self.write(address + BLOB_HEADER_LEN as u16, data)
+    }
+    fn wait_for_write(&mut self, address: u16) -> Result<(), E> {
+        let mut result = Ok(());
+        for _ in 0..WRITE_POLL_RETRIES {
+            result = self.i2c.write(self.address, &address.to_be_bytes());
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,12 @@
+//Memory map of the EEPROM, every region starts with a blob header. build.rs includes this file
+//as well to check that the keymap fits, so it only holds constants.
+pub const SETTINGS_ADDRESS: u16 = 0x0000;
+pub const SETTINGS_SIZE: usize = 64;
+pub const EXPANSION_ADDRESS: u16 = 0x0100;
+pub const EXPANSION_SIZE: usize = 1024;
+pub const MACRO_ADDRESS: u16 = 0x0500;
+pub const MACRO_SIZE: usize = 2048;
+pub const KEYMAP_ADDRESS: u16 = 0x0D00;
+pub const KEYMAP_SIZE: usize = 1024;
+//two magic bytes and the length of the data
+pub const BLOB_HEADER_LEN: usize = 4;
@@ -0,0 +1,345 @@
+use crate::keyboard::ScanableMatrix;
+use bitvec::prelude::*;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+//Matrix behind a 16 bit I2C I/O expander. The rows sit on the first port and get pulled low
+//one after the other, the columns on the second port read low through the diode of a pressed
+//key against the internal pull-ups. Every scan reads all columns of the row selected by the
+//previous one in a single transfer and selects the next row, so a pass over the matrix takes
+//ROWS scans and the row has a whole ms to settle. The two transfers take ~0.2ms at 400kHz.
+//Without an answer from the expander all keys of the matrix get released and the expander is
+//set up again every RETRY_INTERVAL scans until it answers. An expander that lost power but
+//still answers shows up when its pin directions get checked every CHECK_INTERVAL scans.
+//The scan runs in the timer interrupt, so the expander needs an I2C peripheral of its own. An
+//I2cProxy on the I2C0 of the display and EEPROM would find the bus borrowed by the main loop in
+//the middle of a transfer and panic.
+pub const RETRY_INTERVAL: u16 = 100;
+pub const CHECK_INTERVAL: u16 = 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Expander {
+    //IOCON.BANK left at 0, the registers of both ports are next to each other
+    Mcp23017,
+    Pca9555,
+}
+
+impl Expander {
+    //first register of the pair for port A / port 0
+    fn direction(self) -> u8 {
+        match self {
+            Self::Mcp23017 => 0x00,
+            Self::Pca9555 => 0x06,
+        }
+    }
+    fn output(self) -> u8 {
+        match self {
+            Self::Mcp23017 => 0x14,
+            Self::Pca9555 => 0x02,
+        }
+    }
+    fn input(self) -> u8 {
+        match self {
+            Self::Mcp23017 => 0x12,
+            Self::Pca9555 => 0x00,
+        }
+    }
+    //the PCA9555 always has its pull-ups on
+    fn pull_up(self) -> Option<u8> {
+        match self {
+            Self::Mcp23017 => Some(0x0C),
+            Self::Pca9555 => None,
+        }
+    }
+}
+
+pub struct ExpanderMatrix<I2C, const ROWS: usize, const COLS: usize> {
+    i2c: I2C,
+    expander: Expander,
+    address: u8,
+    offset: u8,
+    connected: bool,
+    //selected row
+    row: usize,
+    //scans until the next retry or check
+    wait: u16,
+}
+
+impl<I2C, E, const ROWS: usize, const COLS: usize> ExpanderMatrix<I2C, ROWS, COLS>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub fn new(i2c: I2C, expander: Expander, address: u8, offset: u8) -> Self {
+        assert!(ROWS > 0 && ROWS <= 8 && COLS <= 8);
+        Self {
+            i2c,
+            expander,
+            address,
+            offset,
+            connected: false,
+            row: 0,
+            wait: 0,
+        }
+    }
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+    pub fn free(self) -> I2C {
+        self.i2c
+    }
+    //rows as outputs, the unselected ones high
+    fn directions() -> u8 {
+        (0xFFu16 << ROWS) as u8
+    }
+    fn configure(&mut self) -> Result<(), E> {
+        self.row = 0;
+        self.select()?;
+        self.i2c.write(
+            self.address,
+            &[self.expander.direction(), Self::directions(), 0xFF],
+        )?;
+        if let Some(pull_up) = self.expander.pull_up() {
+            self.i2c.write(self.address, &[pull_up, 0x00, 0xFF])?;
+        }
+        Ok(())
+    }
+    fn configured(&mut self) -> Result<bool, E> {
+        let mut directions = [0u8];
+        self.i2c
+            .write_read(self.address, &[self.expander.direction()], &mut directions)?;
+        Ok(directions[0] == Self::directions())
+    }
+    fn select(&mut self) -> Result<(), E> {
+        self.i2c
+            .write(self.address, &[self.expander.output(), !(1 << self.row)])
+    }
+    //one bit per column, set for a pressed key
+    fn columns(&mut self) -> Result<u8, E> {
+        let mut columns = [0u8];
+        self.i2c
+            .write_read(self.address, &[self.expander.input() + 1], &mut columns)?;
+        Ok(!columns[0])
+    }
+    fn set_key(&self, keybuffer: &mut BitArr!(for 256), key: usize, pressed: bool) {
+        let index = (self.offset as usize + key) * 2;
+        if keybuffer[index] != pressed {
+            keybuffer.set(index, pressed); //key bit
+            keybuffer.set(index + 1, true); //change bit
+        }
+    }
+    fn disconnected(&mut self, keybuffer: &mut BitArr!(for 256)) {
+        self.connected = false;
+        self.wait = RETRY_INTERVAL;
+        for key in 0..ROWS * COLS {
+            self.set_key(keybuffer, key, false);
+        }
+    }
+    fn scan_row(&mut self, keybuffer: &mut BitArr!(for 256)) -> Result<(), E> {
+        if !self.connected {
+            //a replugged expander comes back with all pins as inputs
+            self.configure()?;
+            self.connected = true;
+            self.wait = CHECK_INTERVAL;
+            return Ok(());
+        }
+        if self.wait == 0 {
+            self.wait = CHECK_INTERVAL;
+            if !self.configured()? {
+                self.configure()?;
+                return Ok(());
+            }
+        }
+        let columns = self.columns()?;
+        for col in 0..COLS {
+            self.set_key(keybuffer, self.row * COLS + col, columns & (1 << col) != 0);
+        }
+        self.row = (self.row + 1) % ROWS;
+        self.select()
+    }
+}
+
+impl<I2C, E, const ROWS: usize, const COLS: usize> ScanableMatrix
+    for ExpanderMatrix<I2C, ROWS, COLS>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    fn scan(&mut self, keybuffer: &mut BitArr!(for 256)) {
+        #[cfg(debug_assertions)]
+        {
+            assert!(keybuffer.len() >= (self.offset as usize + ROWS * COLS) * 2);
+        }
+        if self.wait > 0 {
+            self.wait -= 1;
+            if !self.connected {
+                return;
+            }
+        }
+        if self.scan_row(keybuffer).is_err() {
+            self.disconnected(keybuffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i2c_bus::I2cProxy;
+    use core::cell::RefCell;
+    use heapless::Vec;
+
+    const ADDRESS: u8 = 0x20;
+    const OFFSET: u8 = 10;
+
+    //Register file of an expander after power on, pressed has a bit per column for every row.
+    //A pressed key pulls its column low while its row is an output driven low.
+    struct Chip {
+        expander: Expander,
+        registers: [u8; 0x16],
+        pressed: [u8; 8],
+        present: bool,
+        transfers: u32,
+    }
+
+    impl Chip {
+        fn new(expander: Expander, pressed: [u8; 8]) -> Self {
+            let mut registers = [0u8; 0x16];
+            registers[expander.direction() as usize] = 0xFF;
+            registers[expander.direction() as usize + 1] = 0xFF;
+            Self {
+                expander,
+                registers,
+                pressed,
+                present: true,
+                transfers: 0,
+            }
+        }
+        fn columns(&self) -> u8 {
+            if let Some(pull_up) = self.expander.pull_up() {
+                //floating columns without the pull-ups, call them low
+                if self.registers[pull_up as usize + 1] != 0xFF {
+                    return 0;
+                }
+            }
+            let rows = !self.registers[self.expander.direction() as usize];
+            let low = rows & !self.registers[self.expander.output() as usize];
+            let mut columns = 0;
+            for row in 0..8 {
+                if low & (1 << row) != 0 {
+                    columns |= self.pressed[row];
+                }
+            }
+            !columns
+        }
+        fn transfer(&mut self, address: u8) -> Result<(), ()> {
+            assert_eq!(address, ADDRESS);
+            self.transfers += 1;
+            self.present.then_some(()).ok_or(())
+        }
+    }
+
+    impl Write for Chip {
+        type Error = ();
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), ()> {
+            self.transfer(address)?;
+            let first = bytes[0] as usize;
+            self.registers[first..first + bytes.len() - 1].copy_from_slice(&bytes[1..]);
+            Ok(())
+        }
+    }
+
+    impl WriteRead for Chip {
+        type Error = ();
+        fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            self.transfer(address)?;
+            for (register, value) in (bytes[0] as usize..).zip(buffer.iter_mut()) {
+                *value = if register == self.expander.input() as usize + 1 {
+                    self.columns()
+                } else {
+                    self.registers[register]
+                };
+            }
+            Ok(())
+        }
+    }
+
+    fn pressed(keybuffer: &BitArr!(for 256)) -> Vec<usize, 16> {
+        (0..128).filter(|key| keybuffer[key * 2]).collect()
+    }
+
+    fn changed(keybuffer: &mut BitArr!(for 256)) -> Vec<usize, 16> {
+        let keys = (0..128).filter(|key| keybuffer[key * 2 + 1]).collect();
+        for key in 0..128 {
+            keybuffer.set(key * 2 + 1, false);
+        }
+        keys
+    }
+
+    fn scan_matrix(expander: Expander) {
+        let chip = RefCell::new(Chip::new(expander, [0; 8]));
+        let mut matrix: ExpanderMatrix<_, 3, 4> =
+            ExpanderMatrix::new(I2cProxy(&chip), expander, ADDRESS, OFFSET);
+        let mut keybuffer: BitArr!(for 256) = BitArray::ZERO;
+        matrix.scan(&mut keybuffer);
+        assert!(matrix.is_connected());
+        for _ in 0..3 {
+            matrix.scan(&mut keybuffer);
+        }
+        assert!(keybuffer.not_any());
+
+        chip.borrow_mut().pressed[1] = 0b0100;
+        chip.borrow_mut().pressed[2] = 0b1001;
+        chip.borrow_mut().transfers = 0;
+        for _ in 0..3 {
+            matrix.scan(&mut keybuffer);
+        }
+        assert_eq!(pressed(&keybuffer), [16, 18, 21]);
+        //one read and one select per row
+        assert_eq!(chip.borrow().transfers, 6);
+        assert_eq!(changed(&mut keybuffer), [16, 18, 21]);
+
+        chip.borrow_mut().pressed[2] = 0;
+        for _ in 0..3 {
+            matrix.scan(&mut keybuffer);
+        }
+        assert_eq!(pressed(&keybuffer), [16]);
+        assert_eq!(changed(&mut keybuffer), [18, 21]);
+
+        //unplugged, the keys get released
+        chip.borrow_mut().present = false;
+        matrix.scan(&mut keybuffer);
+        assert!(!matrix.is_connected());
+        assert!(pressed(&keybuffer).is_empty());
+        assert_eq!(changed(&mut keybuffer), [16]);
+
+        //plugged back in with the registers reset, set up again after RETRY_INTERVAL
+        let pressed_keys = chip.borrow().pressed;
+        *chip.borrow_mut() = Chip::new(expander, pressed_keys);
+        for _ in 0..RETRY_INTERVAL {
+            matrix.scan(&mut keybuffer);
+            assert!(!matrix.is_connected());
+        }
+        matrix.scan(&mut keybuffer);
+        assert!(matrix.is_connected());
+        for _ in 0..3 {
+            matrix.scan(&mut keybuffer);
+        }
+        assert_eq!(pressed(&keybuffer), [16]);
+        changed(&mut keybuffer);
+
+        //lost power without missing a transfer, found by the direction check
+        *chip.borrow_mut() = Chip::new(expander, pressed_keys);
+        for _ in 0..CHECK_INTERVAL + 4 {
+            matrix.scan(&mut keybuffer);
+        }
+        assert_eq!(pressed(&keybuffer), [16]);
+    }
+
+    #[test]
+    fn mcp23017() {
+        scan_matrix(Expander::Mcp23017);
+    }
+
+    #[test]
+    fn pca9555() {
+        scan_matrix(Expander::Pca9555);
+    }
+}
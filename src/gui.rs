@@ -1,13 +1,24 @@
+use crate::i2c_bus::I2cProxy;
+use crate::keyboard_layouts::{MATRIX_COLS, MATRIX_ROWS};
+use crate::layout::{key_at, matrix_position, HALF_KEYS};
+use crate::matrix_test::MatrixTest;
+use crate::menu::Menu;
+use crate::one_shot::OneShot;
+use crate::settings::Settings;
+#[cfg(feature = "split")]
+use crate::split::{DisplayLine, HalfStatus};
+use core::fmt::Write;
 use embedded_graphics::mono_font::iso_8859_1::FONT_6X10;
 use embedded_graphics::prelude::{Primitive, Size};
-use embedded_graphics::primitives::{PrimitiveStyleBuilder, Rectangle};
+use embedded_graphics::primitives::{PrimitiveStyle, PrimitiveStyleBuilder, Rectangle};
 use embedded_graphics::{
-    mono_font::MonoTextStyle, pixelcolor::BinaryColor, prelude::Point, text::Text, Drawable,
+    mono_font::MonoTextStyle, pixelcolor::BinaryColor, prelude::Point, text::Text, Drawable, Pixel,
 };
 use gd32vf103xx_hal::gpio::{
     gpiob::{PB6, PB7},
     Alternate, OpenDrain,
 };
+use heapless::String;
 use sh1106::prelude::*;
 pub struct StaticGuiElement {
     pub pos: Point,
@@ -44,15 +55,18 @@ macro_rules! StaticGuiElement {
 }
 
 //Oled display
-type Oled = sh1106::mode::GraphicsMode<
+type Oled<'a> = sh1106::mode::GraphicsMode<
     I2cInterface<
-        gd32vf103xx_hal::i2c::BlockingI2c<
-            gd32vf103xx_hal::pac::I2C0,
-            (PB6<Alternate<OpenDrain>>, PB7<Alternate<OpenDrain>>),
+        I2cProxy<
+            'a,
+            gd32vf103xx_hal::i2c::BlockingI2c<
+                gd32vf103xx_hal::pac::I2C0,
+                (PB6<Alternate<OpenDrain>>, PB7<Alternate<OpenDrain>>),
+            >,
         >,
     >,
 >;
-pub fn draw_gui(disp: &mut Oled, s_gui_elem: &[StaticGuiElement]) {
+pub fn draw_gui(disp: &mut Oled<'_>, s_gui_elem: &[StaticGuiElement]) {
     //clear display
     disp.clear();
     //Rechteckfarben
@@ -91,3 +105,238 @@ pub fn draw_gui(disp: &mut Oled, s_gui_elem: &[StaticGuiElement]) {
     //flush changes to display
     disp.flush().unwrap();
 }
+
+//Status line at the bottom, the indicators first, pending one-shots as "S", locked ones as
+//"[S]"
+pub fn draw_one_shots(
+    disp: &mut Oled<'_>,
+    indicators: &str,
+    modifiers: &OneShot,
+    layers: &OneShot,
+) {
+    const NAMES: [&str; 8] = ["C", "S", "A", "G", "RC", "RS", "RA", "RG"];
+    let mut line: String<64> = String::from(indicators);
+    for (i, name) in NAMES.iter().enumerate() {
+        if modifiers.locked() & (1 << i) != 0 {
+            let _ = write!(line, "[{}]", name);
+        } else if modifiers.pending() & (1 << i) != 0 {
+            let _ = write!(line, "{} ", name);
+        }
+    }
+    for layer in 1..8 {
+        if layers.locked() & (1 << layer) != 0 {
+            let _ = write!(line, "[L{}]", layer);
+        } else if layers.pending() & (1 << layer) != 0 {
+            let _ = write!(line, "L{} ", layer);
+        }
+    }
+    Rectangle::new(Point::new(0, 54), Size::new(128, 10))
+        .into_styled(
+            PrimitiveStyleBuilder::new()
+                .fill_color(BinaryColor::Off)
+                .build(),
+        )
+        .draw(disp)
+        .unwrap();
+    Text::new(
+        &line,
+        Point::new(1, 61),
+        MonoTextStyle::new(&FONT_6X10, BinaryColor::On),
+    )
+    .draw(disp)
+    .unwrap();
+    disp.flush().unwrap();
+}
+
+//Keys typed after the leader key, the line above the one-shots
+pub fn draw_leader(disp: &mut Oled<'_>, leader: Option<&str>) {
+    Rectangle::new(Point::new(0, 44), Size::new(128, 10))
+        .into_styled(
+            PrimitiveStyleBuilder::new()
+                .fill_color(BinaryColor::Off)
+                .build(),
+        )
+        .draw(disp)
+        .unwrap();
+    if let Some(keys) = leader {
+        let mut line: String<32> = String::new();
+        let _ = write!(line, "Leader: {}", keys);
+        Text::new(
+            &line,
+            Point::new(1, 51),
+            MonoTextStyle::new(&FONT_6X10, BinaryColor::On),
+        )
+        .draw(disp)
+        .unwrap();
+    }
+    disp.flush().unwrap();
+}
+
+//lines of the menu that fit above the leader line
+const MENU_LINES: usize = 4;
+
+//Settings menu in the upper part, the selected line inverted, cleared once the menu closes.
+//The lines scroll along with the selection.
+pub fn draw_menu(disp: &mut Oled<'_>, menu: &Menu, settings: &Settings) {
+    Rectangle::new(Point::new(0, 0), Size::new(128, 44))
+        .into_styled(
+            PrimitiveStyleBuilder::new()
+                .fill_color(BinaryColor::Off)
+                .build(),
+        )
+        .draw(disp)
+        .unwrap();
+    if menu.is_open() {
+        Text::new(
+            "Menü",
+            Point::new(1, 7),
+            MonoTextStyle::new(&FONT_6X10, BinaryColor::On),
+        )
+        .draw(disp)
+        .unwrap();
+        let first = (menu.selected() as usize + 1).saturating_sub(MENU_LINES);
+        let lines = menu.lines(settings);
+        for (i, line) in lines.iter().enumerate().skip(first).take(MENU_LINES) {
+            let y = 8 + (i - first) as i32 * 9;
+            let selected = menu.selected() as usize == i;
+            Rectangle::new(Point::new(0, y), Size::new(128, 9))
+                .into_styled(
+                    PrimitiveStyleBuilder::new()
+                        .fill_color(if selected {
+                            BinaryColor::On
+                        } else {
+                            BinaryColor::Off
+                        })
+                        .build(),
+                )
+                .draw(disp)
+                .unwrap();
+            Text::new(
+                line,
+                Point::new(1, y + 7),
+                MonoTextStyle::new(
+                    &FONT_6X10,
+                    if selected {
+                        BinaryColor::Off
+                    } else {
+                        BinaryColor::On
+                    },
+                ),
+            )
+            .draw(disp)
+            .unwrap();
+        }
+    }
+    disp.flush().unwrap();
+}
+
+//Page of the secondary half, what the primary sent of its state and the lines of text below
+#[cfg(feature = "split")]
+pub fn draw_secondary(disp: &mut Oled<'_>, status: &HalfStatus, lines: &[DisplayLine]) {
+    disp.clear();
+    let mut layers: String<32> = String::from("Layer");
+    for layer in 0..8 {
+        if status.layers & (1 << layer) != 0 {
+            let _ = write!(layers, " {}", layer);
+        }
+    }
+    let mut leds: String<32> = String::new();
+    for (bit, name) in [(1, "Num"), (2, "Caps"), (0, "Scroll")] {
+        if status.host_leds & (1 << bit) != 0 {
+            let _ = write!(leds, "{} ", name);
+        }
+    }
+    let mut brightness: String<32> = String::new();
+    let _ = write!(brightness, "Brightness {}", status.brightness);
+    let status_lines = [layers.as_str(), leds.as_str(), brightness.as_str()];
+    for (i, line) in status_lines
+        .into_iter()
+        .chain(lines.iter().map(|line| line.as_str()))
+        .enumerate()
+    {
+        Text::new(
+            line,
+            Point::new(1, 7 + i as i32 * 10),
+            MonoTextStyle::new(&FONT_6X10, BinaryColor::On),
+        )
+        .draw(disp)
+        .unwrap();
+    }
+    disp.flush().unwrap();
+}
+
+//Page of the key tester: the pressed keys as half/row/column#key, the key that chattered the
+//most and a grid per half with a dot for every key of the layout, a box for the seen keys,
+//filled while pressed and crossed once it chattered
+pub fn draw_matrix_test(disp: &mut Oled<'_>, test: &MatrixTest) {
+    disp.clear();
+    let mut pressed: String<32> = String::new();
+    for scan_index in test.pressed().take(2) {
+        let (half, row, col) = matrix_position(scan_index);
+        let _ = write!(pressed, "{}/{}/{}#", half, row, col);
+        match key_at(scan_index) {
+            Some(key) => {
+                let _ = write!(pressed, "{} ", key);
+            }
+            None => {
+                let _ = write!(pressed, "- ");
+            }
+        }
+    }
+    let mut seen: String<32> = String::new();
+    let _ = write!(seen, "Seen {}", test.seen_count());
+    if let Some((scan_index, count)) = test.worst_chatter() {
+        let (half, row, col) = matrix_position(scan_index);
+        let _ = write!(seen, " {}/{}/{} x{}", half, row, col, count);
+    }
+    let title = if test.is_leaving() {
+        "Release all keys"
+    } else {
+        "Matrix test"
+    };
+    for (i, line) in [title, pressed.as_str(), seen.as_str()].iter().enumerate() {
+        Text::new(
+            line,
+            Point::new(1, 7 + i as i32 * 10),
+            MonoTextStyle::new(&FONT_6X10, BinaryColor::On),
+        )
+        .draw(disp)
+        .unwrap();
+    }
+    //the right half is half 0, it goes to the right
+    for half in 0..2 {
+        let left = if half == 0 {
+            127 - MATRIX_COLS as i32 * 4
+        } else {
+            1
+        };
+        for row in 0..MATRIX_ROWS {
+            for col in 0..MATRIX_COLS {
+                let scan_index = half * HALF_KEYS + row * MATRIX_COLS + col;
+                let corner = Point::new(left + col as i32 * 4, 32 + row as i32 * 4);
+                if test.chatter(scan_index) > 0 && !test.is_pressed(scan_index) {
+                    for (x, y) in [(0, 0), (2, 0), (1, 1), (0, 2), (2, 2)] {
+                        Pixel(corner + Point::new(x, y), BinaryColor::On)
+                            .draw(disp)
+                            .unwrap();
+                    }
+                } else if test.is_seen(scan_index) {
+                    let style = if test.is_pressed(scan_index) {
+                        PrimitiveStyle::with_fill(BinaryColor::On)
+                    } else {
+                        PrimitiveStyle::with_stroke(BinaryColor::On, 1)
+                    };
+                    Rectangle::new(corner, Size::new(3, 3))
+                        .into_styled(style)
+                        .draw(disp)
+                        .unwrap();
+                } else if key_at(scan_index).is_some() {
+                    Pixel(corner + Point::new(1, 1), BinaryColor::On)
+                        .draw(disp)
+                        .unwrap();
+                }
+            }
+        }
+    }
+    disp.flush().unwrap();
+}
@@ -0,0 +1,58 @@
+use crate::layout::HALF_KEYS;
+use embedded_hal::digital::v2::InputPin;
+
+//Both halves of a split board run the same firmware, the hand only decides where the keys of
+//a half end up in the key buffer
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Hand {
+    Left = 1,
+    Right = 2,
+}
+
+impl Hand {
+    pub fn from_u8(hand: u8) -> Option<Self> {
+        match hand {
+            1 => Some(Self::Left),
+            2 => Some(Self::Right),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Left => "left",
+            Self::Right => "right",
+        }
+    }
+    pub fn other(self) -> Self {
+        match self {
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+        }
+    }
+    pub fn offset(self) -> u8 {
+        match self {
+            Self::Left => HALF_KEYS as u8,
+            Self::Right => 0,
+        }
+    }
+    //The strap pin gets read with the pull-down and then with the pull-up. Tied to GND it is
+    //the left half, tied to VCC the right one, a missing strap follows the pulls and leaves
+    //the hand to the settings.
+    pub fn from_strap(high_pulled_down: bool, high_pulled_up: bool) -> Option<Self> {
+        match (high_pulled_down, high_pulled_up) {
+            (false, false) => Some(Self::Left),
+            (true, true) => Some(Self::Right),
+            _ => None,
+        }
+    }
+}
+
+//The host pulls the PS/2 clock up while the line is idle, the half without a host only sees
+//the pull-down of the pin. The host may hold the clock low for a moment to inhibit the
+//keyboard, so a single high sample is enough.
+pub fn host_present<P: InputPin>(clock: &P, samples: u32, mut wait: impl FnMut()) -> bool {
+    (0..samples).any(|_| {
+        wait();
+        clock.is_high().unwrap_or(false)
+    })
+}
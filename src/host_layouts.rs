@@ -0,0 +1,276 @@
+use crate::keycodes::{KeyCode, KeyCode::*, Modifiers};
+
+//Keyboard layout the host has configured, needed to turn characters into keycodes
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HostLayout {
+    Us,
+    De,
+    Fr,
+    Uk,
+    Dvorak,
+}
+
+#[derive(Clone, Copy)]
+pub struct KeyStroke {
+    pub key: KeyCode,
+    pub modifiers: Modifiers,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Accent {
+    Grave,
+    Acute,
+    Circumflex,
+    Tilde,
+    Diaeresis,
+}
+
+const fn k(c: char, key: KeyCode) -> (char, KeyStroke) {
+    (
+        c,
+        KeyStroke {
+            key,
+            modifiers: Modifiers::NONE,
+        },
+    )
+}
+const fn s(c: char, key: KeyCode) -> (char, KeyStroke) {
+    (
+        c,
+        KeyStroke {
+            key,
+            modifiers: Modifiers::LSHIFT,
+        },
+    )
+}
+//AltGr
+const fn g(c: char, key: KeyCode) -> (char, KeyStroke) {
+    (
+        c,
+        KeyStroke {
+            key,
+            modifiers: Modifiers::RALT,
+        },
+    )
+}
+
+//characters every layout types the same way
+#[rustfmt::skip]
+const COMMON: &[(char, KeyStroke)] = &[
+    k(' ',Space),k('\n',Enter),k('\t',Tab),k('\x08',Backspace),k('\x1B',Escape),
+];
+
+#[rustfmt::skip]
+const US: &[(char, KeyStroke)] = &[
+    k('1',N1),k('2',N2),k('3',N3),k('4',N4),k('5',N5),k('6',N6),k('7',N7),k('8',N8),k('9',N9),k('0',N0),
+    s('!',N1),s('@',N2),s('#',N3),s('$',N4),s('%',N5),s('^',N6),s('&',N7),s('*',N8),s('(',N9),s(')',N0),
+    k('-',Minus),s('_',Minus),k('=',Equal),s('+',Equal),k('[',LeftBracket),s('{',LeftBracket),
+    k(']',RightBracket),s('}',RightBracket),k('\\',Backslash),s('|',Backslash),k(';',Semicolon),
+    s(':',Semicolon),k('\'',Quote),s('"',Quote),k('`',Grave),s('~',Grave),k(',',Comma),s('<',Comma),
+    k('.',Dot),s('>',Dot),k('/',Slash),s('?',Slash),
+];
+
+#[rustfmt::skip]
+const UK: &[(char, KeyStroke)] = &[
+    k('1',N1),k('2',N2),k('3',N3),k('4',N4),k('5',N5),k('6',N6),k('7',N7),k('8',N8),k('9',N9),k('0',N0),
+    s('!',N1),s('"',N2),s('£',N3),s('$',N4),s('%',N5),s('^',N6),s('&',N7),s('*',N8),s('(',N9),s(')',N0),
+    g('€',N4),k('-',Minus),s('_',Minus),k('=',Equal),s('+',Equal),k('[',LeftBracket),
+    s('{',LeftBracket),k(']',RightBracket),s('}',RightBracket),k('#',NonUsHash),s('~',NonUsHash),
+    k('\\',NonUsBackslash),s('|',NonUsBackslash),k(';',Semicolon),s(':',Semicolon),k('\'',Quote),
+    s('@',Quote),k('`',Grave),s('¬',Grave),k(',',Comma),s('<',Comma),k('.',Dot),s('>',Dot),
+    k('/',Slash),s('?',Slash),
+];
+
+#[rustfmt::skip]
+const DE: &[(char, KeyStroke)] = &[
+    k('y',Z),k('z',Y),
+    k('1',N1),k('2',N2),k('3',N3),k('4',N4),k('5',N5),k('6',N6),k('7',N7),k('8',N8),k('9',N9),k('0',N0),
+    s('!',N1),s('"',N2),s('§',N3),s('$',N4),s('%',N5),s('&',N6),s('/',N7),s('(',N8),s(')',N9),s('=',N0),
+    g('²',N2),g('³',N3),g('{',N7),g('[',N8),g(']',N9),g('}',N0),s('°',Grave),
+    k('ß',Minus),s('?',Minus),g('\\',Minus),g('@',Q),g('€',E),g('µ',M),
+    k('ü',LeftBracket),s('Ü',LeftBracket),k('+',RightBracket),s('*',RightBracket),g('~',RightBracket),
+    k('ö',Semicolon),s('Ö',Semicolon),k('ä',Quote),s('Ä',Quote),k('#',NonUsHash),s('\'',NonUsHash),
+    k('<',NonUsBackslash),s('>',NonUsBackslash),g('|',NonUsBackslash),
+    k(',',Comma),s(';',Comma),k('.',Dot),s(':',Dot),k('-',Slash),s('_',Slash),
+];
+#[rustfmt::skip]
+const DE_DEAD: &[(Accent, KeyStroke)] = &[
+    (Accent::Circumflex,k('^',Grave).1),(Accent::Acute,k('´',Equal).1),(Accent::Grave,s('`',Equal).1),
+];
+
+#[rustfmt::skip]
+const FR: &[(char, KeyStroke)] = &[
+    k('a',Q),k('q',A),k('z',W),k('w',Z),k('m',Semicolon),
+    s('1',N1),s('2',N2),s('3',N3),s('4',N4),s('5',N5),s('6',N6),s('7',N7),s('8',N8),s('9',N9),s('0',N0),
+    k('&',N1),k('é',N2),k('"',N3),k('\'',N4),k('(',N5),k('-',N6),k('è',N7),k('_',N8),k('ç',N9),k('à',N0),
+    g('~',N2),g('#',N3),g('{',N4),g('[',N5),g('|',N6),g('`',N7),g('\\',N8),g('^',N9),g('@',N0),
+    k(')',Minus),s('°',Minus),g(']',Minus),k('=',Equal),s('+',Equal),g('}',Equal),k('²',Grave),
+    g('€',E),k('$',RightBracket),s('£',RightBracket),g('¤',RightBracket),k('ù',Quote),s('%',Quote),
+    k('*',NonUsHash),s('µ',NonUsHash),k('<',NonUsBackslash),s('>',NonUsBackslash),
+    k(',',M),s('?',M),k(';',Comma),s('.',Comma),k(':',Dot),s('/',Dot),k('!',Slash),s('§',Slash),
+];
+#[rustfmt::skip]
+const FR_DEAD: &[(Accent, KeyStroke)] = &[
+    (Accent::Circumflex,k('^',LeftBracket).1),(Accent::Diaeresis,s('¨',LeftBracket).1),
+];
+
+//US Dvorak, letters are listed explicitly since they don't sit on their QWERTY keys
+#[rustfmt::skip]
+const DVORAK: &[(char, KeyStroke)] = &[
+    k('a',A),k('b',N),k('c',I),k('d',H),k('e',D),k('f',Y),k('g',U),k('h',J),k('i',G),k('j',C),
+    k('k',V),k('l',P),k('m',M),k('n',L),k('o',S),k('p',R),k('q',X),k('r',O),k('s',Semicolon),
+    k('t',K),k('u',F),k('v',Dot),k('w',Comma),k('x',B),k('y',T),k('z',Slash),
+    k('1',N1),k('2',N2),k('3',N3),k('4',N4),k('5',N5),k('6',N6),k('7',N7),k('8',N8),k('9',N9),k('0',N0),
+    s('!',N1),s('@',N2),s('#',N3),s('$',N4),s('%',N5),s('^',N6),s('&',N7),s('*',N8),s('(',N9),s(')',N0),
+    k('[',Minus),s('{',Minus),k(']',Equal),s('}',Equal),k('\'',Q),s('"',Q),k(',',W),s('<',W),
+    k('.',E),s('>',E),k('/',LeftBracket),s('?',LeftBracket),k('=',RightBracket),s('+',RightBracket),
+    k('-',Quote),s('_',Quote),k(';',Z),s(':',Z),k('\\',Backslash),s('|',Backslash),k('`',Grave),
+    s('~',Grave),
+];
+
+//accented latin-1 characters that can be composed with a dead key
+#[rustfmt::skip]
+const COMPOSED: &[(char, char, Accent)] = &[
+    ('à','a',Accent::Grave),('á','a',Accent::Acute),('â','a',Accent::Circumflex),
+    ('ã','a',Accent::Tilde),('ä','a',Accent::Diaeresis),('è','e',Accent::Grave),
+    ('é','e',Accent::Acute),('ê','e',Accent::Circumflex),('ë','e',Accent::Diaeresis),
+    ('ì','i',Accent::Grave),('í','i',Accent::Acute),('î','i',Accent::Circumflex),
+    ('ï','i',Accent::Diaeresis),('ñ','n',Accent::Tilde),('ò','o',Accent::Grave),
+    ('ó','o',Accent::Acute),('ô','o',Accent::Circumflex),('õ','o',Accent::Tilde),
+    ('ö','o',Accent::Diaeresis),('ù','u',Accent::Grave),('ú','u',Accent::Acute),
+    ('û','u',Accent::Circumflex),('ü','u',Accent::Diaeresis),('ý','y',Accent::Acute),
+    ('ÿ','y',Accent::Diaeresis),
+];
+//the accent on its own is typed as dead key followed by space
+#[rustfmt::skip]
+const SPACING: &[(char, Accent)] = &[
+    ('`',Accent::Grave),('´',Accent::Acute),('^',Accent::Circumflex),('~',Accent::Tilde),
+    ('¨',Accent::Diaeresis),
+];
+
+impl KeyStroke {
+    fn shifted(self) -> Self {
+        Self {
+            key: self.key,
+            modifiers: self.modifiers | Modifiers::LSHIFT,
+        }
+    }
+}
+
+impl HostLayout {
+    pub const ALL: [Self; 5] = [Self::Us, Self::De, Self::Fr, Self::Uk, Self::Dvorak];
+
+    pub fn from_u8(layout: u8) -> Option<Self> {
+        Self::ALL.get(layout as usize).copied()
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Us => "US",
+            Self::De => "DE",
+            Self::Fr => "FR",
+            Self::Uk => "UK",
+            Self::Dvorak => "DVORAK",
+        }
+    }
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+    //characters that need a dead key return it as first stroke
+    pub fn lookup(self, c: char) -> Option<(Option<KeyStroke>, KeyStroke)> {
+        if let Some(stroke) = self.direct(c) {
+            return Some((None, stroke));
+        }
+        if let Some((_, accent)) = SPACING.iter().find(|(s, _)| *s == c) {
+            return Some((Some(self.dead_key(*accent)?), k(' ', Space).1));
+        }
+        let (lower, upper) = match c {
+            //latin-1 upper case letters are 0x20 below their lower case ones
+            'À'..='Þ' if c != '×' => (char::from_u32(c as u32 + 0x20)?, true),
+            _ => (c, false),
+        };
+        let (_, base, accent) = COMPOSED
+            .iter()
+            .find(|(composed, _, _)| *composed == lower)?;
+        let dead = self.dead_key(*accent)?;
+        let stroke = self.direct(*base)?;
+        Some((Some(dead), if upper { stroke.shifted() } else { stroke }))
+    }
+    //character the host types for a key, None for dead keys and shortcuts
+    pub fn character(self, key: KeyCode, modifiers: Modifiers) -> Option<char> {
+        if modifiers.intersects(
+            Modifiers::LCTRL
+                | Modifiers::RCTRL
+                | Modifiers::LALT
+                | Modifiers::LGUI
+                | Modifiers::RGUI,
+        ) {
+            return None;
+        }
+        let shift = modifiers.intersects(Modifiers::LSHIFT | Modifiers::RSHIFT);
+        let mut wanted = modifiers & Modifiers::RALT;
+        if shift {
+            wanted = wanted | Modifiers::LSHIFT;
+        }
+        let table = self.table();
+        let mut strokes = table.iter().chain(COMMON);
+        if let Some((c, _)) = strokes.find(|(_, s)| s.key == key && s.modifiers == wanted) {
+            return Some(*c);
+        }
+        if !(modifiers & Modifiers::RALT).is_empty() {
+            return None;
+        }
+        let lower = match table
+            .iter()
+            .find(|(_, s)| s.key == key && s.modifiers.is_empty())
+        {
+            Some((c, _)) => *c,
+            None => (b'a' + LETTERS.iter().position(|l| *l == key)? as u8) as char,
+        };
+        match (lower.is_ascii_lowercase(), shift) {
+            (_, false) => Some(lower),
+            (true, true) => Some(lower.to_ascii_uppercase()),
+            (false, true) => None,
+        }
+    }
+    fn table(self) -> &'static [(char, KeyStroke)] {
+        match self {
+            HostLayout::Us => US,
+            HostLayout::De => DE,
+            HostLayout::Fr => FR,
+            HostLayout::Uk => UK,
+            HostLayout::Dvorak => DVORAK,
+        }
+    }
+    fn direct(self, c: char) -> Option<KeyStroke> {
+        let table = self.table();
+        if let Some((_, stroke)) = table.iter().chain(COMMON).find(|(t, _)| *t == c) {
+            return Some(*stroke);
+        }
+        if c.is_ascii_uppercase() {
+            return self.direct(c.to_ascii_lowercase()).map(KeyStroke::shifted);
+        }
+        if c.is_ascii_lowercase() {
+            let offset = c as u8 - b'a';
+            return Some(KeyStroke {
+                key: LETTERS[offset as usize],
+                modifiers: Modifiers::NONE,
+            });
+        }
+        None
+    }
+    fn dead_key(self, accent: Accent) -> Option<KeyStroke> {
+        let dead = match self {
+            HostLayout::De => DE_DEAD,
+            HostLayout::Fr => FR_DEAD,
+            _ => &[],
+        };
+        dead.iter()
+            .find(|(a, _)| *a == accent)
+            .map(|(_, stroke)| *stroke)
+    }
+}
+
+#[rustfmt::skip]
+const LETTERS: [KeyCode; 26] = [
+    A,B,C,D,E,F,G,H,I,J,K,L,M,N,O,P,Q,R,S,T,U,V,W,X,Y,Z,
+];
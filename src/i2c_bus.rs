@@ -0,0 +1,29 @@
+use core::cell::RefCell;
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+//Hands out access to one I2C peripheral to several drivers (display and EEPROM)
+pub struct I2cProxy<'a, I2C>(pub &'a RefCell<I2C>);
+
+impl<I2C: Write> Write for I2cProxy<'_, I2C> {
+    type Error = I2C::Error;
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.0.borrow_mut().write(address, bytes)
+    }
+}
+impl<I2C: Read> Read for I2cProxy<'_, I2C> {
+    type Error = I2C::Error;
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.borrow_mut().read(address, buffer)
+    }
+}
+impl<I2C: WriteRead> WriteRead for I2cProxy<'_, I2C> {
+    type Error = I2C::Error;
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.0.borrow_mut().write_read(address, bytes, buffer)
+    }
+}
@@ -0,0 +1,273 @@
+use crate::keyboard::ScanableMatrix;
+use bitvec::prelude::*;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use riscv::asm::delay;
+
+//Direction of the diodes, the current flows from the strobed address pin to the data pins or
+//the other way around
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Diodes {
+    //address driven high, data with pull-downs
+    AddressToData,
+    //address driven low, data with pull-ups
+    DataToAddress,
+}
+
+impl Diodes {
+    pub fn strobe_high(self) -> bool {
+        self == Self::AddressToData
+    }
+    fn strobe<A: OutputPin>(self, address: &mut A) {
+        let _ = if self.strobe_high() {
+            address.set_high()
+        } else {
+            address.set_low()
+        };
+    }
+    fn release<A: OutputPin>(self, address: &mut A) {
+        let _ = if self.strobe_high() {
+            address.set_low()
+        } else {
+            address.set_high()
+        };
+    }
+}
+
+//for pins on any port or behind other drivers, PortMatrix scans GPIO pins faster
+pub struct KeyMatrix<A, D, const AC: usize, const DC: usize>
+where
+    A: OutputPin,
+    D: InputPin,
+{
+    address: [A; AC],
+    data: [D; DC],
+    diodes: Diodes,
+    offset: u8,
+}
+
+impl<A, D, const AC: usize, const DC: usize> KeyMatrix<A, D, AC, DC>
+where
+    A: OutputPin,
+    D: InputPin,
+{
+    pub fn new(address_pins: [A; AC], data_pins: [D; DC], diodes: Diodes, offset: u8) -> Self {
+        let mut matrix = Self {
+            address: address_pins,
+            data: data_pins,
+            diodes,
+            offset,
+        };
+        for addr in matrix.address.iter_mut() {
+            matrix.diodes.release(addr);
+        }
+        matrix
+    }
+}
+impl<A, D, const AC: usize, const DC: usize> ScanableMatrix for KeyMatrix<A, D, AC, DC>
+where
+    A: OutputPin,
+    D: InputPin,
+{
+    fn scan(&mut self, keybuffer: &mut BitArr!(for 256)) {
+        #[cfg(debug_assertions)]
+        {
+            assert!(
+                keybuffer.len() > (self.offset as usize + self.address.len() * self.data.len()) * 2
+            );
+        }
+        let data_len = self.data.len();
+        let strobe_high = self.diodes.strobe_high();
+        for (i, addr) in self.address.iter_mut().enumerate() {
+            self.diodes.strobe(addr);
+            for (j, dat) in self.data.iter().enumerate() {
+                match dat.is_high() {
+                    Err(_) => {}
+                    //the data pin follows the strobed address pin
+                    Ok(r) => set_key(
+                        keybuffer,
+                        self.offset as usize + i * data_len + j,
+                        r == strobe_high,
+                    ),
+                }
+            }
+            self.diodes.release(addr);
+            unsafe {
+                delay(100); //wait for diode capacitance to discharge ~1µS should be sufficient, if
+                            //not a keypress will register as the current and following key.
+            }
+        }
+    }
+}
+
+//One pin per key shorted to GND by the switch, for macro pads and thumb clusters
+pub struct DirectPins<P, const N: usize>
+where
+    P: InputPin,
+{
+    pins: [P; N],
+    offset: u8,
+}
+
+impl<P, const N: usize> DirectPins<P, N>
+where
+    P: InputPin,
+{
+    //the pins need their pull-ups, see pu_input!
+    pub fn new(pins: [P; N], offset: u8) -> Self {
+        Self { pins, offset }
+    }
+}
+impl<P, const N: usize> ScanableMatrix for DirectPins<P, N>
+where
+    P: InputPin,
+{
+    fn scan(&mut self, keybuffer: &mut BitArr!(for 256)) {
+        #[cfg(debug_assertions)]
+        {
+            assert!(keybuffer.len() >= (self.offset as usize + N) * 2);
+        }
+        for (i, pin) in self.pins.iter().enumerate() {
+            if let Ok(low) = pin.is_low() {
+                set_key(keybuffer, self.offset as usize + i, low);
+            }
+        }
+    }
+}
+
+fn set_key(keybuffer: &mut BitArr!(for 256), key: usize, pressed: bool) {
+    let bits = keybuffer.get_mut(key * 2..=(key * 2 + 1)).unwrap();
+    if pressed != *bits.get(0).unwrap() {
+        bits.set(0, pressed); //key bit
+        bits.set(1, true); //change bit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+    use heapless::Vec;
+
+    const OFFSET: usize = 5;
+
+    //Address pin levels and the pressed keys as (address, data) pairs. A pressed key connects
+    //its data pin to its address pin through the diode, the pull holds the data pin otherwise.
+    struct Board {
+        diodes: Diodes,
+        address: [bool; 3],
+        pressed: Vec<(usize, usize), 8>,
+    }
+
+    impl Board {
+        fn data(&self, data: usize) -> bool {
+            let strobe = self.diodes.strobe_high();
+            let strobed: Vec<usize, 3> = (0..3).filter(|&i| self.address[i] == strobe).collect();
+            //only one address pin strobed at a time, or keys show up on the wrong one
+            assert_eq!(strobed.len(), 1);
+            let pressed = self.pressed.contains(&(strobed[0], data));
+            pressed == strobe
+        }
+    }
+
+    struct AddressPin<'a>(&'a RefCell<Board>, usize);
+
+    impl OutputPin for AddressPin<'_> {
+        type Error = Infallible;
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().address[self.1] = false;
+            Ok(())
+        }
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().address[self.1] = true;
+            Ok(())
+        }
+    }
+
+    struct DataPin<'a>(&'a RefCell<Board>, usize);
+
+    impl InputPin for DataPin<'_> {
+        type Error = Infallible;
+        fn is_high(&self) -> Result<bool, Infallible> {
+            Ok(self.0.borrow().data(self.1))
+        }
+        fn is_low(&self) -> Result<bool, Infallible> {
+            Ok(!self.0.borrow().data(self.1))
+        }
+    }
+
+    fn keys(keybuffer: &BitArr!(for 256), bit: usize) -> Vec<usize, 8> {
+        (0..128).filter(|key| keybuffer[key * 2 + bit]).collect()
+    }
+
+    fn scan_matrix(diodes: Diodes) {
+        let board = RefCell::new(Board {
+            diodes,
+            //floating before the matrix takes the pins
+            address: [true, false, true],
+            pressed: Vec::new(),
+        });
+        let mut matrix = KeyMatrix::new(
+            [0, 1, 2].map(|i| AddressPin(&board, i)),
+            [0, 1, 2, 3].map(|i| DataPin(&board, i)),
+            diodes,
+            OFFSET as u8,
+        );
+        let released = !diodes.strobe_high();
+        assert_eq!(board.borrow().address, [released; 3]);
+        let mut keybuffer: BitArr!(for 256) = BitArray::ZERO;
+        matrix.scan(&mut keybuffer);
+        assert!(keybuffer.not_any());
+
+        board.borrow_mut().pressed = Vec::from_slice(&[(0, 3), (2, 0), (1, 1)]).unwrap();
+        matrix.scan(&mut keybuffer);
+        let pressed = [OFFSET + 3, OFFSET + 4 + 1, OFFSET + 8];
+        assert_eq!(keys(&keybuffer, 0), pressed);
+        assert_eq!(keys(&keybuffer, 1), pressed);
+        assert_eq!(board.borrow().address, [released; 3]);
+
+        keybuffer.fill(false);
+        for key in pressed {
+            keybuffer.set(key * 2, true);
+        }
+        board.borrow_mut().pressed = Vec::from_slice(&[(2, 0)]).unwrap();
+        matrix.scan(&mut keybuffer);
+        assert_eq!(keys(&keybuffer, 0), [OFFSET + 8]);
+        assert_eq!(keys(&keybuffer, 1), [OFFSET + 3, OFFSET + 4 + 1]);
+    }
+
+    #[test]
+    fn address_to_data() {
+        scan_matrix(Diodes::AddressToData);
+    }
+
+    #[test]
+    fn data_to_address() {
+        scan_matrix(Diodes::DataToAddress);
+    }
+
+    struct Switch<'a>(&'a RefCell<[bool; 4]>, usize);
+
+    impl InputPin for Switch<'_> {
+        type Error = Infallible;
+        fn is_high(&self) -> Result<bool, Infallible> {
+            Ok(!self.0.borrow()[self.1])
+        }
+        fn is_low(&self) -> Result<bool, Infallible> {
+            Ok(self.0.borrow()[self.1])
+        }
+    }
+
+    #[test]
+    fn direct_pins() {
+        let closed = RefCell::new([false; 4]);
+        let mut pins = DirectPins::new([0, 1, 2, 3].map(|i| Switch(&closed, i)), OFFSET as u8);
+        let mut keybuffer: BitArr!(for 256) = BitArray::ZERO;
+        pins.scan(&mut keybuffer);
+        assert!(keybuffer.not_any());
+        *closed.borrow_mut() = [false, true, false, true];
+        pins.scan(&mut keybuffer);
+        assert_eq!(keys(&keybuffer, 0), [OFFSET + 1, OFFSET + 3]);
+        assert_eq!(keys(&keybuffer, 1), [OFFSET + 1, OFFSET + 3]);
+    }
+}
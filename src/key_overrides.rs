@@ -0,0 +1,38 @@
+use crate::keycodes::{KeyCode, Modifiers};
+
+//Sends another key when a key is pressed together with certain modifiers, e.g. Shift+Backspace
+//as Delete. The trigger modifiers are let go of while the replacement is down.
+#[derive(Clone, Copy)]
+pub struct KeyOverride {
+    pub trigger: KeyCode,
+    //at least one of them has to be held
+    pub modifiers: Modifiers,
+    //none of them may be held
+    pub negative: Modifiers,
+    //bit per layer the override works on
+    pub layers: u8,
+    pub replacement: KeyCode,
+    //pressed together with the replacement
+    pub replacement_modifiers: Modifiers,
+}
+
+impl KeyOverride {
+    //modifiers the host gets with the replacement
+    pub fn modifiers(&self, modifiers: Modifiers) -> Modifiers {
+        (modifiers & !self.modifiers) | self.replacement_modifiers
+    }
+}
+
+pub fn find(
+    overrides: &'static [KeyOverride],
+    code: KeyCode,
+    modifiers: Modifiers,
+    layers: u8,
+) -> Option<&'static KeyOverride> {
+    overrides.iter().find(|o| {
+        o.trigger == code
+            && o.layers & layers != 0
+            && modifiers.intersects(o.modifiers)
+            && !modifiers.intersects(o.negative)
+    })
+}
@@ -1,10 +1,32 @@
-use crate::ps2::PS2;
-use crate::{keyboard_layouts::SCANCODE_LOOKUP, sprintln};
+use crate::auto_shift::{AutoShift, AutoShiftOutput};
+use crate::combos::ComboDetector;
+use crate::eeprom::{Eeprom, BLOB_HEADER_LEN, MACRO_SIZE};
+use crate::key_overrides;
+use crate::keyboard_layouts::{
+    Action, AUTO_SHIFT, CAPS_WORD, COMBOS, KEY_COUNT, KEY_OVERRIDES, LAYOUT, LEADER,
+    LEADER_SEQUENCES, MACROS, NUM_WORD, TAP_DANCES, TAP_HOLDS,
+};
+use crate::keycodes::{KeyCode, Modifiers};
+use crate::keymap::Keymap;
+use crate::leader::{Leader, LeaderResult, MAX_LEADER_KEYS};
+use crate::macros::{MacroOutput, MacroPlayer, MacroSource};
+use crate::matrix_test::{MatrixTest, BOOT_SCANS, SCAN_KEYS};
+use crate::menu::Menu;
+use crate::one_shot::OneShot;
+use crate::ps2::{Typematic, PS2};
+use crate::scancodes::{ScancodeSet, Scancodes};
+use crate::settings::Settings;
+use crate::storage::Storage;
+use crate::tap_hold::{Behavior, Hold, Resolved, Role, TapHoldResolver};
+use crate::vendor::{Received, Session};
+use crate::word_modes::{CapsWord, NumWord};
+use crate::{get_millis, sprintln};
 use bitvec::prelude::*;
 use core::convert::Infallible;
-use embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use embedded_hal::digital::v2::{InputPin, StatefulOutputPin};
+use heapless::{String, Vec};
 use ringbuffer::{ConstGenericRingBuffer, RingBuffer, RingBufferRead, RingBufferWrite};
-use riscv::asm::delay;
 
 pub struct Keyboard<M, const MC: usize, Ps2Data, Ps2Clock>
 where
@@ -14,12 +36,53 @@ where
 {
     matricies: [M; MC],
     ps2_interface: PS2<Ps2Data, Ps2Clock>,
-    key_buffer: BitArr!(for 192),
+    key_buffer: BitArr!(for 256),
+    //time of the last change of every key, taken by the scan
+    key_times: [u32; KEY_COUNT],
     scancode_buffer: ConstGenericRingBuffer<u8, 32>,
     command_buffer: ConstGenericRingBuffer<u8, 32>,
     enabled_scanning: bool,
+    //command whose argument byte comes next
+    argument_for: Option<u8>,
+    typematic: Typematic,
+    scancodes: Scancodes,
+    //lock LEDs set by the host, bit 0 ScrollLock, bit 1 NumLock, bit 2 CapsLock
+    host_leds: u8,
+    //modifiers physically held down
+    modifiers: Modifiers,
+    //modifiers the host currently sees as pressed
+    host_modifiers: Modifiers,
+    macro_player: MacroPlayer,
+    combos: ComboDetector,
+    tap_hold: TapHoldResolver,
+    //bit per active layer, layer 0 is always active
+    layers: u8,
+    one_shot_modifiers: OneShot,
+    one_shot_layers: OneShot,
+    leader: Leader,
+    caps_word: CapsWord,
+    num_word: NumWord,
+    auto_shift: AutoShift,
+    //keys that are down as another key, by the layout emulation or a key override
+    replaced: Vec<(KeyCode, KeyCode), 4>,
+    menu: Menu,
+    //takes the keys instead of the host while it runs
+    matrix_test: MatrixTest,
+    //the keys held at boot may start the key tester
+    boot_checked: bool,
+    //action each key and combo got when it was pressed
+    key_actions: [Action; KEY_COUNT + COMBOS.len()],
+    settings: Settings,
+    //changed in the menu, saved by the main loop
+    settings_unsaved: bool,
+    storage: Storage,
+    //the macro store is the largest region the host can upload
+    vendor: Session<{ MACRO_SIZE - BLOB_HEADER_LEN }>,
 }
 
+//free bytes in the scancode buffer that macro playback leaves for live typing
+const MACRO_HEADROOM: usize = 8;
+
 impl<M, const MC: usize, Ps2Data, Ps2Clock> Keyboard<M, MC, Ps2Data, Ps2Clock>
 where
     M: ScanableMatrix,
@@ -29,29 +92,118 @@ where
     pub fn new(matricies: [M; MC], ps2_data: Ps2Data, ps2_clock: Ps2Clock) -> Self {
         let mut kb = Self {
             matricies,
-            key_buffer: bitarr!(usize,Lsb0;0;192),
+            key_buffer: bitarr!(usize,Lsb0;0;256),
+            key_times: [0; KEY_COUNT],
             scancode_buffer: ConstGenericRingBuffer::new(),
             command_buffer: ConstGenericRingBuffer::new(),
             ps2_interface: PS2::new(ps2_data, ps2_clock),
             enabled_scanning: false,
+            argument_for: None,
+            typematic: Typematic::DEFAULT,
+            scancodes: Scancodes::new(),
+            host_leds: 0,
+            modifiers: Modifiers::NONE,
+            host_modifiers: Modifiers::NONE,
+            macro_player: MacroPlayer::new(),
+            combos: ComboDetector::new(),
+            tap_hold: TapHoldResolver::new(),
+            layers: 0,
+            one_shot_modifiers: OneShot::new(),
+            one_shot_layers: OneShot::new(),
+            leader: Leader::new(),
+            caps_word: CapsWord::new(),
+            num_word: NumWord::new(),
+            auto_shift: AutoShift::new(),
+            replaced: Vec::new(),
+            menu: Menu::new(),
+            matrix_test: MatrixTest::new(),
+            boot_checked: false,
+            key_actions: [Action::No; KEY_COUNT + COMBOS.len()],
+            settings: Settings::new(),
+            settings_unsaved: false,
+            storage: Storage::new(),
+            vendor: Session::new(),
         };
         kb.scancode_buffer.push(0xAA);
         kb
     }
     pub fn scan(&mut self) {
+        let before = self.key_buffer;
         for matrix in &mut self.matricies {
             matrix.scan(&mut self.key_buffer);
         }
+        let now = get_millis();
+        if self.matrix_test.is_active() {
+            for scan_index in 0..SCAN_KEYS {
+                let pressed = self.key_buffer[scan_index * 2];
+                if pressed != before[scan_index * 2] {
+                    self.matrix_test.event(scan_index, pressed, now);
+                }
+                //the keys don't go any further, not even after the test
+                self.key_buffer.set(scan_index * 2 + 1, false);
+            }
+            self.matrix_test.poll(&self.key_buffer, now);
+            return;
+        }
+        for (time, physical) in self.key_times.iter_mut().zip(&LAYOUT) {
+            let index = physical.scan_index() * 2;
+            if self.key_buffer[index] != before[index] {
+                *time = now;
+            }
+        }
     }
     fn send_ack(&mut self) {
         self.scancode_buffer.push(0xFA);
     }
     pub fn process_keystrokes(&mut self) {
-        if !self.command_buffer.is_empty() {
-            let command = self.command_buffer.dequeue().unwrap();
+        let now = get_millis();
+        self.vendor.poll(now);
+        while let Some(command) = self.command_buffer.dequeue() {
+            match self
+                .vendor
+                .receive(command, now, &mut self.storage, &mut self.scancode_buffer)
+            {
+                Received::NotVendor => {}
+                Received::Unlocking => {
+                    self.send_ack();
+                    if self.vendor.is_open() {
+                        sprintln!("Vendor session opened");
+                        self.release_for_session(now);
+                    }
+                    continue;
+                }
+                Received::Session => continue,
+            }
             sprintln!("Received {:#02x}", command);
+            //a byte with the high bit set is a new command instead of the argument
+            if let Some(argument_for) = self.argument_for.take() {
+                if command < 0x80 {
+                    self.send_ack();
+                    match argument_for {
+                        0xF3 => self.typematic = Typematic::from_byte(command),
+                        //LED bits, NumLock changes the fake shifts of the navigation keys
+                        0xED => {
+                            self.host_leds = command & 0x07;
+                            self.scancodes.num_lock = command & 0x02 != 0;
+                        }
+                        0xF0 if command == 0 => {
+                            self.scancode_buffer.push(self.scancodes.set as u8);
+                        }
+                        0xF0 => {
+                            if let Some(set) = ScancodeSet::from_u8(command) {
+                                self.scancodes.set = set;
+                            }
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+            }
             match command {
                 0xFF => {
+                    self.typematic = Typematic::DEFAULT;
+                    self.scancodes = Scancodes::new();
+                    self.host_leds = 0;
                     self.send_ack();
                     self.scancode_buffer.push(0xAA);
                 }
@@ -66,7 +218,8 @@ where
                     self.enabled_scanning = true;
                     self.send_ack();
                 }
-                0xF3 => {
+                0xF3 | 0xF0 | 0xED => {
+                    self.argument_for = Some(command);
                     self.send_ack();
                 }
                 0xF2 => {
@@ -74,52 +227,470 @@ where
                     self.scancode_buffer.push(0xAB);
                     self.scancode_buffer.push(0x83);
                 }
-                0xF0 => {
-                    self.send_ack();
-                }
                 0xEE => {
                     self.scancode_buffer.push(0xEE);
                 }
-                0xED => {
-                    self.send_ack();
-                }
                 _ => {
                     self.send_ack();
                 }
             }
         }
+        //the host still gets its answers while booting and during the matrix test, only the keys
+        //wait. The time counts the scans.
+        if !self.boot_checked {
+            if now < BOOT_SCANS {
+                return;
+            }
+            self.boot_checked = true;
+            if MatrixTest::exit_keys_held(&self.key_buffer) {
+                self.matrix_test.start(&self.key_buffer);
+            }
+        }
+        if self.matrix_test.is_active() {
+            return;
+        }
+        //keys are left alone until the host is done, the key buffer keeps their state
+        if self.vendor.is_open() {
+            return;
+        }
+        if self.one_shot_modifiers.poll(now) && !self.macro_player.is_playing() {
+            self.sync_modifiers(self.live_modifiers());
+        }
+        self.one_shot_layers.poll(now);
+        self.caps_word.poll(now, &CAPS_WORD);
+        self.num_word.poll(now, &NUM_WORD);
+        if let Some(action) = self.leader.poll(now, &LEADER_SEQUENCES, LEADER) {
+            self.tap_action(action, now);
+        }
+        let layers = self.active_layers();
         //        if self.enabled_scanning {
-        for i in (0..self.key_buffer.len()).step_by(2) {
+        //spots of the matrix without a key in the layout are never looked at
+        for (key, physical) in LAYOUT.iter().enumerate() {
+            let i = physical.scan_index() * 2;
             let val = self.key_buffer.get_mut(i..=i + 1).unwrap();
             // read change bit
             if *val.get(1).unwrap() {
-                let key = i / 2;
-                if key >= SCANCODE_LOOKUP.len() {
-                    continue;
+                let pressed = *val.get(0).unwrap();
+                //the change bit stays set if the combos are full, the key is retried next time
+                let time = self.key_times[key];
+                if self.combos.event(&COMBOS, layers, key as u8, pressed, time) {
+                    val.set(1, false);
                 }
-                if *val.get(0).unwrap() {
-                    sprintln!("Key {} pressed", key);
-                    for code in SCANCODE_LOOKUP[key] {
-                        self.scancode_buffer.push(*code);
-                    }
-                } else if SCANCODE_LOOKUP[key].len() == 1 {
-                    self.scancode_buffer.push(0xF0); //break code
-                    self.scancode_buffer.push(SCANCODE_LOOKUP[key][0]);
-                    sprintln!("Key {} released", key);
+            }
+            //          }
+        }
+        self.combos.poll(&COMBOS, layers, now);
+        while let Some((key, pressed, time)) = self.combos.next_output() {
+            if !self.tap_hold.event(key, pressed, time) {
+                break;
+            }
+            self.combos.commit();
+        }
+        //looked up again for every event, the ones before may have switched layers
+        loop {
+            let layers = self.active_layers();
+            let keymap = &self.storage.keymap;
+            let resolved =
+                self.tap_hold
+                    .next(now, |key| match action(keymap, layers, key as usize) {
+                        Action::TapHold(id) => TAP_HOLDS
+                            .get(id as usize)
+                            .map(|t| Behavior::TapHold(t.config)),
+                        Action::TapDance(id) => TAP_DANCES
+                            .get(id as usize)
+                            .map(|d| Behavior::TapDance(d.term, d.steps.len() as u8)),
+                        _ => None,
+                    });
+            match resolved {
+                Some(resolved) => self.resolved_event(resolved, now),
+                None => break,
+            }
+        }
+        if let Some(output) = self.auto_shift.poll(now, &AUTO_SHIFT, self.typematic) {
+            self.auto_shift_output(output, now);
+        }
+        self.play_macro(now);
+    }
+    fn resolved_event(&mut self, resolved: Resolved, now: u32) {
+        let key = resolved.key as usize;
+        let pressed = resolved.pressed;
+        //a release belongs to the action the key was pressed with, even if the layer changed
+        let action = if pressed {
+            self.key_actions[key] = action(&self.storage.keymap, self.active_layers(), key);
+            self.key_actions[key]
+        } else {
+            self.key_actions[key]
+        };
+        if pressed {
+            sprintln!("Key {} pressed", key);
+        } else {
+            sprintln!("Key {} released", key);
+        }
+        if pressed && self.menu.is_open() {
+            if let Some(code) = typed_key(action, resolved.role) {
+                self.key_actions[key] = Action::No;
+                self.settings_unsaved |= self.menu.key(code, &mut self.settings);
+                if self.menu.take_matrix_test() {
+                    self.matrix_test.start(&self.key_buffer);
+                }
+                return;
+            }
+        }
+        if pressed && self.leader.is_active() {
+            if let Some(code) = typed_key(action, resolved.role) {
+                //the release belongs to the sequence as well
+                self.key_actions[key] = Action::No;
+                match self.leader.key(code, now, &LEADER_SEQUENCES, LEADER) {
+                    LeaderResult::Done(action) => self.tap_action(action, now),
+                    LeaderResult::Pending | LeaderResult::Failed => {}
+                }
+                return;
+            }
+        }
+        if !self.auto_shift_event(key, action, resolved.role, pressed, now) {
+            self.action_event(action, resolved.role, pressed, now);
+        }
+        if pressed && uses_one_shots(action, resolved.role) {
+            self.one_shot_modifiers.key_used();
+            self.one_shot_layers.key_used();
+        }
+        if pressed {
+            self.num_word.key(action, now, &NUM_WORD);
+        }
+    }
+    //returns true if auto-shift took the event
+    fn auto_shift_event(
+        &mut self,
+        key: usize,
+        action: Action,
+        role: Role,
+        pressed: bool,
+        now: u32,
+    ) -> bool {
+        if !pressed {
+            let time = self.key_times.get(key).copied().unwrap_or(now);
+            return match self.auto_shift.release(key as u8, time, &AUTO_SHIFT) {
+                Some(output) => {
+                    self.auto_shift_output(output, now);
+                    true
+                }
+                None => false,
+            };
+        }
+        if let Some(code) = self.auto_shift.interrupt() {
+            self.auto_shift_output(AutoShiftOutput::Tap(code, false), now);
+        }
+        match action {
+            //shortcuts and combos go out right away
+            Action::Key(code)
+                if self.settings.auto_shift
+                    && role == Role::Plain
+                    && key < KEY_COUNT
+                    && self.live_modifiers() == Modifiers::NONE
+                    && AUTO_SHIFT.applies(code) =>
+            {
+                self.auto_shift.press(key as u8, code, self.key_times[key]);
+                true
+            }
+            _ => false,
+        }
+    }
+    fn auto_shift_output(&mut self, output: AutoShiftOutput, now: u32) {
+        match output {
+            AutoShiftOutput::Tap(code, shifted) => {
+                self.press_key(code, shifted, now);
+                self.key_event(code, false, now);
+            }
+            AutoShiftOutput::Press(code) => self.press_key(code, true, now),
+            AutoShiftOutput::Repeat(code) => {
+                self.scancodes
+                    .make(&mut self.scancode_buffer, code, self.host_modifiers)
+            }
+            AutoShiftOutput::Release(code) => self.key_event(code, false, now),
+        }
+    }
+    fn action_event(&mut self, action: Action, role: Role, pressed: bool, now: u32) {
+        //typed text isn't part of the word
+        if pressed && matches!(action, Action::Macro(_) | Action::Unicode(_)) {
+            self.caps_word.cancel();
+        }
+        match action {
+            Action::No | Action::Transparent => {}
+            Action::Key(code) => self.key_event(code, pressed, now),
+            Action::Macro(_) if !pressed => {}
+            Action::Macro(id) => {
+                if self.macro_player.is_playing() {
+                    self.macro_player.cancel();
                 } else {
-                    //Extended code
-                    self.scancode_buffer.push(0xE0);
-                    self.scancode_buffer.push(0xF0);
-                    for code in &SCANCODE_LOOKUP[key][1..] {
-                        self.scancode_buffer.push(*code);
+                    //uploaded macros take the place of the builtin ones
+                    let source = self.storage.macros.find(id).or_else(|| {
+                        MACROS
+                            .get(id as usize)
+                            .map(|steps| MacroSource::Builtin(steps))
+                    });
+                    if let Some(source) = source {
+                        self.macro_player.start(source, now);
                     }
-                    sprintln!("Key {} released", key);
                 }
-
-                val.set(1, false);
             }
-            //          }
+            Action::Unicode(c) => {
+                if pressed && !self.macro_player.is_playing() {
+                    self.macro_player.start_char(c, self.settings.profile());
+                }
+            }
+            Action::TapHold(id) => {
+                if let Some(tap_hold) = TAP_HOLDS.get(id as usize) {
+                    match (role, tap_hold.hold) {
+                        (Role::Hold, Hold::Modifier(code)) => self.key_event(code, pressed, now),
+                        (Role::Hold, Hold::Layer(layer)) => self.layer_event(layer, pressed),
+                        _ => self.key_event(tap_hold.tap, pressed, now),
+                    }
+                }
+            }
+            Action::TapDance(id) => {
+                let steps = TAP_DANCES.get(id as usize).map_or(&[][..], |d| d.steps);
+                if let (Role::Dance(count, held), Some(last)) = (role, steps.last()) {
+                    let step = steps.get(count as usize - 1).unwrap_or(last);
+                    let action = if held { step.hold } else { step.tap };
+                    //steps can't wait for a decision themselves
+                    if !matches!(action, Action::TapDance(_)) {
+                        self.action_event(action, Role::Tap, pressed, now);
+                    }
+                }
+            }
+            Action::Layer(layer) => self.layer_event(layer, pressed),
+            Action::OneShotModifiers(modifiers) => {
+                if pressed {
+                    self.one_shot_modifiers.press(modifiers.0, now);
+                } else {
+                    self.one_shot_modifiers.release(modifiers.0, now);
+                }
+                if !self.macro_player.is_playing() {
+                    self.sync_modifiers(self.live_modifiers());
+                }
+            }
+            Action::CapsWord => {
+                if pressed {
+                    self.caps_word.toggle(now);
+                }
+            }
+            Action::NumWord(layer) => {
+                if pressed {
+                    self.num_word.toggle(layer, now);
+                }
+            }
+            Action::Leader => {
+                if pressed {
+                    self.leader.start(now);
+                }
+            }
+            Action::Menu => {
+                if pressed {
+                    self.menu.toggle();
+                }
+            }
+            Action::OneShotLayer(layer) => {
+                if pressed {
+                    self.one_shot_layers.press(1 << layer, now);
+                } else {
+                    self.one_shot_layers.release(1 << layer, now);
+                }
+            }
+        }
+    }
+    fn tap_action(&mut self, action: Action, now: u32) {
+        self.action_event(action, Role::Tap, true, now);
+        self.action_event(action, Role::Tap, false, now);
+    }
+    fn active_layers(&self) -> u8 {
+        self.layers | self.one_shot_layers.active() | self.num_word.layers() | 1
+    }
+    //modifiers held down or pending as one-shots
+    fn live_modifiers(&self) -> Modifiers {
+        self.modifiers | Modifiers(self.one_shot_modifiers.active())
+    }
+    fn layer_event(&mut self, layer: u8, pressed: bool) {
+        if pressed {
+            self.layers |= 1 << layer;
+        } else {
+            self.layers &= !(1 << layer);
+        }
+    }
+    fn key_event(&mut self, code: KeyCode, pressed: bool, now: u32) {
+        if code.is_modifier() {
+            if pressed {
+                self.modifiers = self.modifiers | code.modifier();
+            } else {
+                self.modifiers = self.modifiers & !code.modifier();
+            }
+            //while a macro is running the modifiers get synced before the next live key
+            if !self.macro_player.is_playing() {
+                self.sync_modifiers(self.live_modifiers());
+            }
+        } else if pressed {
+            self.press_key(code, false, now);
+        } else {
+            let code = match self.replaced.iter().position(|(typed, _)| *typed == code) {
+                Some(i) => self.replaced.swap_remove(i).1,
+                None => code,
+            };
+            self.scancodes
+                .release(&mut self.scancode_buffer, code, self.host_modifiers);
+            //used up one-shots are released together with the key
+            if !self.macro_player.is_playing() && self.host_modifiers != self.live_modifiers() {
+                self.sync_modifiers(self.live_modifiers());
+            }
+        }
+    }
+    fn press_key(&mut self, typed: KeyCode, shifted: bool, now: u32) {
+        let mut modifiers = self.live_modifiers();
+        let mut code = typed;
+        //without room to remember the replacement the key goes out as it is
+        let replace = !self.replaced.is_full();
+        let shortcut = modifiers.intersects(Modifiers::CTRL | Modifiers::ALT | Modifiers::GUI);
+        if replace && !(shortcut && self.settings.shortcut_passthrough) {
+            code = self.settings.emulated_layout.remap(code);
+        }
+        if self.caps_word.key(code, modifiers, now, &CAPS_WORD) || shifted {
+            modifiers = modifiers | Modifiers::LSHIFT;
+        }
+        let layers = self.active_layers();
+        if let Some(o) = key_overrides::find(&KEY_OVERRIDES, code, modifiers, layers) {
+            if replace {
+                modifiers = o.modifiers(modifiers);
+                code = o.replacement;
+            }
+        }
+        if code != typed {
+            let _ = self.replaced.push((typed, code));
+        }
+        self.sync_modifiers(modifiers);
+        self.scancodes
+            .make(&mut self.scancode_buffer, code, self.host_modifiers);
+        if self.macro_player.is_playing() {
+            self.storage.text_expansion.reset();
+        } else if let Some((backspaces, expansion)) = self.storage.text_expansion.key_pressed(
+            code,
+            modifiers,
+            self.settings.profile().host_layout,
+        ) {
+            self.macro_player
+                .start_expansion(backspaces, expansion, now);
+        }
+    }
+    fn sync_modifiers(&mut self, modifiers: Modifiers) {
+        self.scancodes
+            .modifier_change(&mut self.scancode_buffer, self.host_modifiers, modifiers);
+        self.host_modifiers = modifiers;
+    }
+    fn play_macro(&mut self, now: u32) {
+        while let Some(output) =
+            self.macro_player
+                .next_output(now, self.settings.profile(), &self.storage.macros)
+        {
+            let mut codes: Vec<u8, 32> = Vec::new();
+            let modifiers = match output {
+                MacroOutput::Press(code, modifiers) => {
+                    self.scancodes
+                        .modifier_change(&mut codes, self.host_modifiers, modifiers);
+                    self.scancodes.make(&mut codes, code, modifiers);
+                    modifiers
+                }
+                MacroOutput::Release(code) => {
+                    self.scancodes
+                        .release(&mut codes, code, self.host_modifiers);
+                    self.host_modifiers
+                }
+            };
+            //don't let the ringbuffer overwrite unsent bytes, wait for the interface instead
+            let free = self.scancode_buffer.capacity() - self.scancode_buffer.len();
+            if codes.len() > free
+                || (free - codes.len() < MACRO_HEADROOM && !self.scancode_buffer.is_empty())
+            {
+                return;
+            }
+            for code in codes {
+                self.scancode_buffer.push(code);
+            }
+            self.host_modifiers = modifiers;
+            self.macro_player.commit(&self.storage.macros);
+        }
+        if !self.macro_player.is_playing() && self.host_modifiers != self.live_modifiers() {
+            self.sync_modifiers(self.live_modifiers());
+        }
+    }
+    //the keys wait until a vendor session closes, nothing may stay pressed on the host meanwhile
+    fn release_for_session(&mut self, now: u32) {
+        self.macro_player.cancel();
+        //all that is left of a cancelled macro are the releases of the keys it holds
+        while let Some(output) =
+            self.macro_player
+                .next_output(now, self.settings.profile(), &self.storage.macros)
+        {
+            if let MacroOutput::Release(code) = output {
+                self.scancodes
+                    .release(&mut self.scancode_buffer, code, self.host_modifiers);
+            }
+            self.macro_player.commit(&self.storage.macros);
+        }
+        self.sync_modifiers(Modifiers::NONE);
+    }
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+    pub fn menu(&self) -> &Menu {
+        &self.menu
+    }
+    pub fn matrix_test(&self) -> &MatrixTest {
+        &self.matrix_test
+    }
+    pub fn matrix_test_mut(&mut self) -> &mut MatrixTest {
+        &mut self.matrix_test
+    }
+    //settings changed in the menu get written once it is closed
+    pub fn save_settings<I2C, E>(&mut self, eeprom: &mut Eeprom<I2C>) -> Result<(), E>
+    where
+        I2C: Write<Error = E> + WriteRead<Error = E>,
+    {
+        if !self.settings_unsaved || self.menu.is_open() {
+            return Ok(());
         }
+        self.settings_unsaved = false;
+        self.settings.save(eeprom)
+    }
+    //state the primary sends down to the other half
+    #[cfg(feature = "split")]
+    pub fn layers(&self) -> u8 {
+        self.active_layers()
+    }
+    #[cfg(feature = "split")]
+    pub fn host_leds(&self) -> u8 {
+        self.host_leds
+    }
+    #[cfg(feature = "split")]
+    pub fn matricies_mut(&mut self) -> &mut [M; MC] {
+        &mut self.matricies
+    }
+    pub fn caps_word_active(&self) -> bool {
+        self.caps_word.is_active()
+    }
+    pub fn one_shot_modifiers(&self) -> &OneShot {
+        &self.one_shot_modifiers
+    }
+    pub fn one_shot_layers(&self) -> &OneShot {
+        &self.one_shot_layers
+    }
+    //keys typed after the leader key, None if there is no leader sequence going on
+    pub fn leader_text(&self) -> Option<String<{ MAX_LEADER_KEYS * 4 }>> {
+        self.leader
+            .is_active()
+            .then(|| self.leader.text(self.settings.profile().host_layout))
+    }
+    pub fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
     }
     pub fn update_interface(&mut self) {
         self.ps2_interface
@@ -127,6 +698,39 @@ where
     }
 }
 
+//everything that sends something counts as the next key of a one-shot
+fn uses_one_shots(action: Action, role: Role) -> bool {
+    match action {
+        Action::Key(code) => !code.is_modifier(),
+        Action::TapHold(_) => role != Role::Hold,
+        Action::Macro(_) | Action::Unicode(_) | Action::TapDance(_) => true,
+        Action::No
+        | Action::Transparent
+        | Action::Layer(_)
+        | Action::OneShotModifiers(_)
+        | Action::OneShotLayer(_)
+        | Action::Leader
+        | Action::CapsWord
+        | Action::NumWord(_)
+        | Action::Menu => false,
+    }
+}
+//key a press types for the leader and the menu, modifiers and layer keys keep working meanwhile
+fn typed_key(action: Action, role: Role) -> Option<KeyCode> {
+    match action {
+        Action::Key(code) if !code.is_modifier() => Some(code),
+        Action::TapHold(id) if role != Role::Hold => TAP_HOLDS.get(id as usize).map(|t| t.tap),
+        _ => None,
+    }
+}
+//matrix keys come from the keymap, the keys after them are the combos
+fn action(keymap: &Keymap, layers: u8, key: usize) -> Action {
+    match key.checked_sub(KEY_COUNT) {
+        None => keymap.get(layers, key),
+        Some(id) => COMBOS.get(id).map_or(Action::No, |combo| combo.action),
+    }
+}
+
 #[macro_export]
 macro_rules! pp_output {
     ($($pin:expr),+) => {
@@ -139,74 +743,21 @@ macro_rules! pd_input {
         [$($pin.into_pull_down_input().downgrade()),+]
     };
 }
-
-pub trait ScanableMatrix {
-    fn scan(&mut self, keybuffer: &mut BitArr!(for 192));
+#[macro_export]
+macro_rules! pu_input {
+    ($($pin:expr),+) => {
+        [$($pin.into_pull_up_input().downgrade()),+]
+    };
 }
 
-pub struct KeyMatrix<A, D, const AC: usize, const DC: usize>
-where
-    A: OutputPin,
-    D: InputPin,
-{
-    address: [A; AC],
-    data: [D; DC],
-    offset: u8,
+pub trait ScanableMatrix {
+    fn scan(&mut self, keybuffer: &mut BitArr!(for 256));
 }
 
-impl<A, D, const AC: usize, const DC: usize> KeyMatrix<A, D, AC, DC>
-where
-    A: OutputPin,
-    D: InputPin,
-{
-    pub fn new(address_pins: [A; AC], data_pins: [D; DC], offset: u8) -> Self {
-        Self {
-            address: address_pins,
-            data: data_pins,
-            offset,
-        }
-    }
-}
-impl<A, D, const AC: usize, const DC: usize> ScanableMatrix for KeyMatrix<A, D, AC, DC>
-where
-    A: OutputPin,
-    D: InputPin,
-{
-    fn scan(&mut self, keybuffer: &mut BitArr!(for 192)) {
-        #[cfg(debug_assertions)]
-        {
-            assert!(
-                keybuffer.len() > (self.offset as usize + self.address.len() * self.data.len()) * 2
-            );
-        }
-        let address_len = self.address.len();
-        for (i, addr) in self.address.iter_mut().enumerate() {
-            let _ = addr.set_high();
-            for (j, dat) in self.data.iter().enumerate() {
-                match dat.is_high() {
-                    Err(_) => {}
-                    Ok(r) => {
-                        let key = (self.offset as usize + i * address_len + j) * 2;
-                        let bits = keybuffer.get_mut(key..=(key + 1)).unwrap();
-                        if r {
-                            //key wasn't already pressed
-                            if !*bits.get(0).unwrap() {
-                                bits.set(0, true); //key bit
-                                bits.set(1, true); //change bit
-                            }
-                        } else if *bits.get(0).unwrap() {
-                            //key was pressed before
-                            bits.set(0, false);
-                            bits.set(1, true);
-                        }
-                    }
-                }
-            }
-            let _ = addr.set_low();
-            unsafe {
-                delay(100); //wait for diode capacitance to discharge ~1µS should be sufficient, if
-                            //not a keypress will register as the current and following key.
-            }
-        }
+//two different kinds of matrices, like the half wired to the MCU and the link to the other one
+impl<A: ScanableMatrix, B: ScanableMatrix> ScanableMatrix for (A, B) {
+    fn scan(&mut self, keybuffer: &mut BitArr!(for 256)) {
+        self.0.scan(keybuffer);
+        self.1.scan(keybuffer);
     }
 }
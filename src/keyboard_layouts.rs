@@ -1,8 +1,173 @@
-pub const SCANCODE_LOOKUP: [&[u8]; 42] = [
-    &[0x5A],&[0x83],&[0x0A],&[0x01],&[0x09],&[0x78],&[0x07],
-    &[0x12],&[0x36],&[0x3D],&[0x3E],&[0x46],&[0x45],&[0x4E],
-    &[0x29],&[0x2B],&[0x34],&[0x21],&[0x2C],&[0x35],&[0x4A],
-    &[0x0D],&[0x33],&[0x23],&[0x2D],&[0x31],&[0x1B],&[0x61],
-    &[0x14],&[0x32],&[0x3A],&[0x1D],&[0x2A],&[0x4B],&[0xE0,0x12,0xE0,0x7c],
-    &[0xE0,0x11],&[0xE0,0x6B],&[0xE0,0x75],&[0xE0,0x72],&[0xE0,0x74],&[0xE0,0x7D],&[0xE0,0x7A]
+use crate::auto_shift::AutoShiftConfig;
+use crate::combos::{keys, Combo, ComboRelease};
+use crate::key_overrides::KeyOverride;
+use crate::keycodes::{
+    KeyCode::{self, *},
+    Modifiers,
+};
+use crate::layout::PhysicalKey;
+use crate::leader::{LeaderConfig, LeaderSequence};
+use crate::macros::MacroStep::{self, *};
+use crate::tap_hold::{DanceStep, Flavor, Hold, TapDance, TapHold, TapHoldConfig};
+use crate::word_modes::{CapsWordConfig, NumWordConfig};
+
+//KEY_COUNT, LAYER_COUNT, MATRIX_ROWS, MATRIX_COLS, LAYOUT, KEYMAP and TAP_HOLDS, build.rs
+//generates them from keymap.toml
+include!(concat!(env!("OUT_DIR"), "/keymap.rs"));
+
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum Action {
+    No,
+    Key(KeyCode),
+    //starts playback of MACROS[n], cancels the running macro if there is one
+    Macro(u8),
+    //typed like a character of a Text macro step
+    Unicode(char),
+    //TAP_HOLDS[n]
+    TapHold(u8),
+    //TAP_DANCES[n]
+    TapDance(u8),
+    //layer active while the key is held
+    Layer(u8),
+    //uses the action of the next lower active layer
+    Transparent,
+    //applied to the next key only, see one_shot.rs
+    OneShotModifiers(Modifiers),
+    OneShotLayer(u8),
+    //the following keys are matched against LEADER_SEQUENCES
+    Leader,
+    //toggle, see word_modes.rs
+    CapsWord,
+    NumWord(u8),
+    //opens and closes the settings menu on the OLED
+    Menu,
+}
+use Action::*;
+
+impl Action {
+    //4 byte encoding used for keymaps stored in the EEPROM
+    pub fn encode(self) -> [u8; 4] {
+        match self {
+            Action::No => [0, 0, 0, 0],
+            Key(code) => [1, code as u8, 0, 0],
+            Macro(id) => [2, id, 0, 0],
+            Action::Unicode(c) => {
+                let code = (c as u32).to_le_bytes();
+                [3, code[0], code[1], code[2]]
+            }
+            TapHold(id) => [4, id, 0, 0],
+            Layer(layer) => [5, layer, 0, 0],
+            Transparent => [6, 0, 0, 0],
+            TapDance(id) => [7, id, 0, 0],
+            OneShotModifiers(modifiers) => [8, modifiers.0, 0, 0],
+            OneShotLayer(layer) => [9, layer, 0, 0],
+            Leader => [10, 0, 0, 0],
+            CapsWord => [11, 0, 0, 0],
+            NumWord(layer) => [12, layer, 0, 0],
+            Menu => [13, 0, 0, 0],
+        }
+    }
+    pub fn decode(data: [u8; 4]) -> Option<Self> {
+        match data[0] {
+            0 => Some(Action::No),
+            1 => Some(Key(KeyCode::from_u8(data[1])?)),
+            2 => Some(Macro(data[1])),
+            3 => Some(Action::Unicode(char::from_u32(u32::from_le_bytes([
+                data[1], data[2], data[3], 0,
+            ]))?)),
+            4 => Some(TapHold(data[1])),
+            5 if (data[1] as usize) < LAYER_COUNT => Some(Layer(data[1])),
+            6 => Some(Transparent),
+            7 => Some(TapDance(data[1])),
+            8 => Some(OneShotModifiers(Modifiers(data[1]))),
+            9 if (data[1] as usize) < LAYER_COUNT => Some(OneShotLayer(data[1])),
+            10 => Some(Leader),
+            11 => Some(CapsWord),
+            12 if (data[1] as usize) < LAYER_COUNT => Some(NumWord(data[1])),
+            13 => Some(Menu),
+            _ => None,
+        }
+    }
+}
+
+//timing of the LT keys
+const LAYER_TAP: TapHoldConfig = TapHoldConfig {
+    tapping_term: 200,
+    quick_tap_term: 150,
+    flavor: Flavor::HoldOnOtherKeyPress,
+    retro_tap: false,
+};
+//home row mods get rolled over a lot while typing, only a full press inside counts as hold
+const HOME_ROW_MOD: TapHoldConfig = TapHoldConfig {
+    tapping_term: 220,
+    quick_tap_term: 150,
+    flavor: Flavor::PermissiveHold,
+    retro_tap: true,
+};
+
+#[rustfmt::skip]
+pub const TAP_DANCES: [TapDance; 1] = [
+    TapDance { term: 200, steps: &[
+        DanceStep { tap: Key(Semicolon), hold: Layer(1) },
+        DanceStep { tap: Action::Unicode(':'), hold: Action::No },
+    ] },
+];
+
+pub const KEY_OVERRIDES: [KeyOverride; 1] = [KeyOverride {
+    trigger: Backspace,
+    modifiers: Modifiers::SHIFT,
+    negative: Modifiers(Modifiers::CTRL.0 | Modifiers::ALT.0 | Modifiers::GUI.0),
+    layers: 0b11,
+    replacement: Delete,
+    replacement_modifiers: Modifiers::NONE,
+}];
+
+//keys are indices into the [layout] of keymap.toml, layer bit 0 is the base layer
+#[rustfmt::skip]
+pub const COMBOS: [Combo; 3] = [
+    Combo { keys: keys(&[15, 16]), action: Key(Escape), layers: 0b01, release: ComboRelease::AllReleased, term: 50 },
+    Combo { keys: keys(&[17, 18]), action: Key(Backspace), layers: 0b01, release: ComboRelease::FirstReleased, term: 50 },
+    Combo { keys: keys(&[15, 16, 17]), action: Key(Delete), layers: 0b01, release: ComboRelease::AllReleased, term: 60 },
+];
+
+//minus gives the underscore of CONSTANTS_LIKE_THIS on US, UK and Dvorak hosts
+pub const CAPS_WORD: CapsWordConfig = CapsWordConfig {
+    timeout: 5000,
+    shifted: &[Minus],
+    continues: &[N1, N2, N3, N4, N5, N6, N7, N8, N9, N0, Backspace, Delete],
+};
+pub const NUM_WORD: NumWordConfig = NumWordConfig {
+    timeout: 5000,
+    continues: &[Dot, Comma, Minus, Equal, Backspace, Delete],
+};
+
+//keys the auto-shift of the menu holds back while it is on
+pub const AUTO_SHIFT: AutoShiftConfig = AutoShiftConfig {
+    timeout: 175,
+    letters: true,
+    numbers: true,
+    symbols: false,
+    repeat: true,
+};
+
+pub const LEADER: LeaderConfig = LeaderConfig {
+    timeout: 800,
+    per_key: true,
+};
+
+#[rustfmt::skip]
+pub const LEADER_SEQUENCES: [LeaderSequence; 3] = [
+    LeaderSequence { keys: &[G, S], action: Macro(3) },
+    LeaderSequence { keys: &[G, D], action: Macro(4) },
+    LeaderSequence { keys: &[S], action: Action::Unicode('ß') },
+];
+
+#[rustfmt::skip]
+pub const MACROS: [&[MacroStep]; 5] = [
+    &[Press(LShift),Tap(H),Release(LShift),Tap(E),Tap(L),Tap(L),Tap(O)],
+    &[Text("Grüße, Menü")],
+    &[Text("x "),MacroStep::Unicode('≤'),Text(" 2")],
+    &[Text("git status")],
+    &[Text("git diff")],
 ];
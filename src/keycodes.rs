@@ -0,0 +1,546 @@
+use core::ops::{BitAnd, BitOr, Not};
+
+//HID keyboard usage ids (usage page 0x07), the set-2 codes are only a transport detail
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum KeyCode {
+    No = 0x00,
+    A = 0x04,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    N1 = 0x1E,
+    N2,
+    N3,
+    N4,
+    N5,
+    N6,
+    N7,
+    N8,
+    N9,
+    N0,
+    Enter = 0x28,
+    Escape,
+    Backspace,
+    Tab,
+    Space,
+    Minus,
+    Equal,
+    LeftBracket,
+    RightBracket,
+    Backslash,
+    NonUsHash,
+    Semicolon,
+    Quote,
+    Grave,
+    Comma,
+    Dot,
+    Slash,
+    CapsLock,
+    F1 = 0x3A,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    PrintScreen = 0x46,
+    ScrollLock,
+    Pause,
+    Insert,
+    Home,
+    PageUp,
+    Delete,
+    End,
+    PageDown,
+    Right,
+    Left,
+    Down,
+    Up,
+    NumLock = 0x53,
+    KpSlash,
+    KpAsterisk,
+    KpMinus,
+    KpPlus,
+    KpEnter,
+    Kp1,
+    Kp2,
+    Kp3,
+    Kp4,
+    Kp5,
+    Kp6,
+    Kp7,
+    Kp8,
+    Kp9,
+    Kp0,
+    KpDot,
+    NonUsBackslash = 0x64,
+    Application,
+    LCtrl = 0xE0,
+    LShift,
+    LAlt,
+    LGui,
+    RCtrl,
+    RShift,
+    RAlt,
+    RGui,
+}
+
+impl KeyCode {
+    pub fn from_u8(code: u8) -> Option<Self> {
+        match code {
+            //the enum has no holes inside these ranges
+            0x00 | 0x04..=0x65 | 0xE0..=0xE7 => {
+                Some(unsafe { core::mem::transmute::<u8, Self>(code) })
+            }
+            _ => None,
+        }
+    }
+    //set-2 make code, without the fake shifts PrintScreen and the navigation keys get, see
+    //scancodes.rs
+    pub fn set2(self) -> &'static [u8] {
+        use KeyCode::*;
+        match self {
+            No => &[],
+            A => &[0x1C],
+            B => &[0x32],
+            C => &[0x21],
+            D => &[0x23],
+            E => &[0x24],
+            F => &[0x2B],
+            G => &[0x34],
+            H => &[0x33],
+            I => &[0x43],
+            J => &[0x3B],
+            K => &[0x42],
+            L => &[0x4B],
+            M => &[0x3A],
+            N => &[0x31],
+            O => &[0x44],
+            P => &[0x4D],
+            Q => &[0x15],
+            R => &[0x2D],
+            S => &[0x1B],
+            T => &[0x2C],
+            U => &[0x3C],
+            V => &[0x2A],
+            W => &[0x1D],
+            X => &[0x22],
+            Y => &[0x35],
+            Z => &[0x1A],
+            N1 => &[0x16],
+            N2 => &[0x1E],
+            N3 => &[0x26],
+            N4 => &[0x25],
+            N5 => &[0x2E],
+            N6 => &[0x36],
+            N7 => &[0x3D],
+            N8 => &[0x3E],
+            N9 => &[0x46],
+            N0 => &[0x45],
+            Enter => &[0x5A],
+            Escape => &[0x76],
+            Backspace => &[0x66],
+            Tab => &[0x0D],
+            Space => &[0x29],
+            Minus => &[0x4E],
+            Equal => &[0x55],
+            LeftBracket => &[0x54],
+            RightBracket => &[0x5B],
+            Backslash => &[0x5D],
+            NonUsHash => &[0x5D],
+            Semicolon => &[0x4C],
+            Quote => &[0x52],
+            Grave => &[0x0E],
+            Comma => &[0x41],
+            Dot => &[0x49],
+            Slash => &[0x4A],
+            CapsLock => &[0x58],
+            F1 => &[0x05],
+            F2 => &[0x06],
+            F3 => &[0x04],
+            F4 => &[0x0C],
+            F5 => &[0x03],
+            F6 => &[0x0B],
+            F7 => &[0x83],
+            F8 => &[0x0A],
+            F9 => &[0x01],
+            F10 => &[0x09],
+            F11 => &[0x78],
+            F12 => &[0x07],
+            PrintScreen => &[0xE0, 0x7C],
+            ScrollLock => &[0x7E],
+            Pause => &[0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77],
+            Insert => &[0xE0, 0x70],
+            Home => &[0xE0, 0x6C],
+            PageUp => &[0xE0, 0x7D],
+            Delete => &[0xE0, 0x71],
+            End => &[0xE0, 0x69],
+            PageDown => &[0xE0, 0x7A],
+            Right => &[0xE0, 0x74],
+            Left => &[0xE0, 0x6B],
+            Down => &[0xE0, 0x72],
+            Up => &[0xE0, 0x75],
+            NumLock => &[0x77],
+            KpSlash => &[0xE0, 0x4A],
+            KpAsterisk => &[0x7C],
+            KpMinus => &[0x7B],
+            KpPlus => &[0x79],
+            KpEnter => &[0xE0, 0x5A],
+            Kp1 => &[0x69],
+            Kp2 => &[0x72],
+            Kp3 => &[0x7A],
+            Kp4 => &[0x6B],
+            Kp5 => &[0x73],
+            Kp6 => &[0x74],
+            Kp7 => &[0x6C],
+            Kp8 => &[0x75],
+            Kp9 => &[0x7D],
+            Kp0 => &[0x70],
+            KpDot => &[0x71],
+            NonUsBackslash => &[0x61],
+            Application => &[0xE0, 0x2F],
+            LCtrl => &[0x14],
+            LShift => &[0x12],
+            LAlt => &[0x11],
+            LGui => &[0xE0, 0x1F],
+            RCtrl => &[0xE0, 0x14],
+            RShift => &[0x59],
+            RAlt => &[0xE0, 0x11],
+            RGui => &[0xE0, 0x27],
+        }
+    }
+    //set-1 make code, the break code has bit 7 set
+    pub fn set1(self) -> &'static [u8] {
+        use KeyCode::*;
+        match self {
+            No => &[],
+            A => &[0x1E],
+            B => &[0x30],
+            C => &[0x2E],
+            D => &[0x20],
+            E => &[0x12],
+            F => &[0x21],
+            G => &[0x22],
+            H => &[0x23],
+            I => &[0x17],
+            J => &[0x24],
+            K => &[0x25],
+            L => &[0x26],
+            M => &[0x32],
+            N => &[0x31],
+            O => &[0x18],
+            P => &[0x19],
+            Q => &[0x10],
+            R => &[0x13],
+            S => &[0x1F],
+            T => &[0x14],
+            U => &[0x16],
+            V => &[0x2F],
+            W => &[0x11],
+            X => &[0x2D],
+            Y => &[0x15],
+            Z => &[0x2C],
+            N1 => &[0x02],
+            N2 => &[0x03],
+            N3 => &[0x04],
+            N4 => &[0x05],
+            N5 => &[0x06],
+            N6 => &[0x07],
+            N7 => &[0x08],
+            N8 => &[0x09],
+            N9 => &[0x0A],
+            N0 => &[0x0B],
+            Enter => &[0x1C],
+            Escape => &[0x01],
+            Backspace => &[0x0E],
+            Tab => &[0x0F],
+            Space => &[0x39],
+            Minus => &[0x0C],
+            Equal => &[0x0D],
+            LeftBracket => &[0x1A],
+            RightBracket => &[0x1B],
+            Backslash => &[0x2B],
+            NonUsHash => &[0x2B],
+            Semicolon => &[0x27],
+            Quote => &[0x28],
+            Grave => &[0x29],
+            Comma => &[0x33],
+            Dot => &[0x34],
+            Slash => &[0x35],
+            CapsLock => &[0x3A],
+            F1 => &[0x3B],
+            F2 => &[0x3C],
+            F3 => &[0x3D],
+            F4 => &[0x3E],
+            F5 => &[0x3F],
+            F6 => &[0x40],
+            F7 => &[0x41],
+            F8 => &[0x42],
+            F9 => &[0x43],
+            F10 => &[0x44],
+            F11 => &[0x57],
+            F12 => &[0x58],
+            PrintScreen => &[0xE0, 0x37],
+            ScrollLock => &[0x46],
+            Pause => &[0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5],
+            Insert => &[0xE0, 0x52],
+            Home => &[0xE0, 0x47],
+            PageUp => &[0xE0, 0x49],
+            Delete => &[0xE0, 0x53],
+            End => &[0xE0, 0x4F],
+            PageDown => &[0xE0, 0x51],
+            Right => &[0xE0, 0x4D],
+            Left => &[0xE0, 0x4B],
+            Down => &[0xE0, 0x50],
+            Up => &[0xE0, 0x48],
+            NumLock => &[0x45],
+            KpSlash => &[0xE0, 0x35],
+            KpAsterisk => &[0x37],
+            KpMinus => &[0x4A],
+            KpPlus => &[0x4E],
+            KpEnter => &[0xE0, 0x1C],
+            Kp1 => &[0x4F],
+            Kp2 => &[0x50],
+            Kp3 => &[0x51],
+            Kp4 => &[0x4B],
+            Kp5 => &[0x4C],
+            Kp6 => &[0x4D],
+            Kp7 => &[0x47],
+            Kp8 => &[0x48],
+            Kp9 => &[0x49],
+            Kp0 => &[0x52],
+            KpDot => &[0x53],
+            NonUsBackslash => &[0x56],
+            Application => &[0xE0, 0x5D],
+            LCtrl => &[0x1D],
+            LShift => &[0x2A],
+            LAlt => &[0x38],
+            LGui => &[0xE0, 0x5B],
+            RCtrl => &[0xE0, 0x1D],
+            RShift => &[0x36],
+            RAlt => &[0xE0, 0x38],
+            RGui => &[0xE0, 0x5C],
+        }
+    }
+    //set-3 make code, every key makes and breaks
+    pub fn set3(self) -> &'static [u8] {
+        use KeyCode::*;
+        match self {
+            No => &[],
+            A => &[0x1C],
+            B => &[0x32],
+            C => &[0x21],
+            D => &[0x23],
+            E => &[0x24],
+            F => &[0x2B],
+            G => &[0x34],
+            H => &[0x33],
+            I => &[0x43],
+            J => &[0x3B],
+            K => &[0x42],
+            L => &[0x4B],
+            M => &[0x3A],
+            N => &[0x31],
+            O => &[0x44],
+            P => &[0x4D],
+            Q => &[0x15],
+            R => &[0x2D],
+            S => &[0x1B],
+            T => &[0x2C],
+            U => &[0x3C],
+            V => &[0x2A],
+            W => &[0x1D],
+            X => &[0x22],
+            Y => &[0x35],
+            Z => &[0x1A],
+            N1 => &[0x16],
+            N2 => &[0x1E],
+            N3 => &[0x26],
+            N4 => &[0x25],
+            N5 => &[0x2E],
+            N6 => &[0x36],
+            N7 => &[0x3D],
+            N8 => &[0x3E],
+            N9 => &[0x46],
+            N0 => &[0x45],
+            Enter => &[0x5A],
+            Escape => &[0x08],
+            Backspace => &[0x66],
+            Tab => &[0x0D],
+            Space => &[0x29],
+            Minus => &[0x4E],
+            Equal => &[0x55],
+            LeftBracket => &[0x54],
+            RightBracket => &[0x5B],
+            Backslash => &[0x5C],
+            NonUsHash => &[0x53],
+            Semicolon => &[0x4C],
+            Quote => &[0x52],
+            Grave => &[0x0E],
+            Comma => &[0x41],
+            Dot => &[0x49],
+            Slash => &[0x4A],
+            CapsLock => &[0x14],
+            F1 => &[0x07],
+            F2 => &[0x0F],
+            F3 => &[0x17],
+            F4 => &[0x1F],
+            F5 => &[0x27],
+            F6 => &[0x2F],
+            F7 => &[0x37],
+            F8 => &[0x3F],
+            F9 => &[0x47],
+            F10 => &[0x4F],
+            F11 => &[0x56],
+            F12 => &[0x5E],
+            PrintScreen => &[0x57],
+            ScrollLock => &[0x5F],
+            Pause => &[0x62],
+            Insert => &[0x67],
+            Home => &[0x6E],
+            PageUp => &[0x6F],
+            Delete => &[0x64],
+            End => &[0x65],
+            PageDown => &[0x6D],
+            Right => &[0x6A],
+            Left => &[0x61],
+            Down => &[0x60],
+            Up => &[0x63],
+            NumLock => &[0x76],
+            KpSlash => &[0x77],
+            KpAsterisk => &[0x7E],
+            KpMinus => &[0x84],
+            KpPlus => &[0x7C],
+            KpEnter => &[0x79],
+            Kp1 => &[0x69],
+            Kp2 => &[0x72],
+            Kp3 => &[0x7A],
+            Kp4 => &[0x6B],
+            Kp5 => &[0x73],
+            Kp6 => &[0x74],
+            Kp7 => &[0x6C],
+            Kp8 => &[0x75],
+            Kp9 => &[0x7D],
+            Kp0 => &[0x70],
+            KpDot => &[0x71],
+            NonUsBackslash => &[0x13],
+            Application => &[0x8D],
+            LCtrl => &[0x11],
+            LShift => &[0x12],
+            LAlt => &[0x19],
+            LGui => &[0x8B],
+            RCtrl => &[0x58],
+            RShift => &[0x59],
+            RAlt => &[0x39],
+            RGui => &[0x8C],
+        }
+    }
+    pub fn modifier(self) -> Modifiers {
+        let code = self as u8;
+        if code >= KeyCode::LCtrl as u8 {
+            Modifiers(1 << (code - KeyCode::LCtrl as u8))
+        } else {
+            Modifiers::NONE
+        }
+    }
+    pub fn is_modifier(self) -> bool {
+        self.modifier() != Modifiers::NONE
+    }
+}
+
+//Modifier bits in HID report order
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Modifiers(pub u8);
+
+#[allow(dead_code)]
+impl Modifiers {
+    pub const NONE: Self = Self(0);
+    pub const LCTRL: Self = Self(1 << 0);
+    pub const LSHIFT: Self = Self(1 << 1);
+    pub const LALT: Self = Self(1 << 2);
+    pub const LGUI: Self = Self(1 << 3);
+    pub const RCTRL: Self = Self(1 << 4);
+    pub const RSHIFT: Self = Self(1 << 5);
+    pub const RALT: Self = Self(1 << 6);
+    pub const RGUI: Self = Self(1 << 7);
+    //either side
+    pub const CTRL: Self = Self(Self::LCTRL.0 | Self::RCTRL.0);
+    pub const SHIFT: Self = Self(Self::LSHIFT.0 | Self::RSHIFT.0);
+    pub const ALT: Self = Self(Self::LALT.0 | Self::RALT.0);
+    pub const GUI: Self = Self(Self::LGUI.0 | Self::RGUI.0);
+    const KEYS: [KeyCode; 8] = [
+        KeyCode::LCtrl,
+        KeyCode::LShift,
+        KeyCode::LAlt,
+        KeyCode::LGui,
+        KeyCode::RCtrl,
+        KeyCode::RShift,
+        KeyCode::RAlt,
+        KeyCode::RGui,
+    ];
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+    pub fn keycodes(self) -> impl Iterator<Item = KeyCode> {
+        Self::KEYS
+            .into_iter()
+            .enumerate()
+            .filter(move |(i, _)| self.0 & (1 << i) != 0)
+            .map(|(_, key)| key)
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+impl BitAnd for Modifiers {
+    type Output = Self;
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+impl Not for Modifiers {
+    type Output = Self;
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
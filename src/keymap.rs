@@ -0,0 +1,69 @@
+use crate::eeprom::{Eeprom, BLOB_HEADER_LEN, KEYMAP_ADDRESS, KEYMAP_SIZE};
+use crate::keyboard_layouts::{Action, KEYMAP, KEY_COUNT, LAYER_COUNT};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use heapless::Vec;
+
+const MAGIC: [u8; 2] = *b"KM";
+
+//Keymap in use, starts out as the compiled in one and can be replaced by the host
+pub struct Keymap {
+    layers: [[Action; KEY_COUNT]; LAYER_COUNT],
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Self { layers: KEYMAP }
+    }
+    //action of the highest active layer that isn't transparent, layers is a bit per layer
+    pub fn get(&self, layers: u8, key: usize) -> Action {
+        (0..LAYER_COUNT)
+            .rev()
+            .filter(|layer| layer == &0 || layers & (1 << layer) != 0)
+            .filter_map(|layer| self.layers[layer].get(key))
+            .find(|action| !matches!(action, Action::Transparent))
+            .copied()
+            .unwrap_or(Action::No)
+    }
+    pub fn encoded(&self) -> impl Iterator<Item = u8> + '_ {
+        self.layers
+            .iter()
+            .flatten()
+            .flat_map(|action| action.encode())
+    }
+    pub fn encoded_len(&self) -> usize {
+        LAYER_COUNT * KEY_COUNT * 4
+    }
+    //takes a complete keymap, nothing is changed if any action doesn't decode
+    pub fn set_encoded(&mut self, data: &[u8]) -> bool {
+        if data.len() != self.encoded_len() {
+            return false;
+        }
+        let mut layers = self.layers;
+        for (action, code) in layers.iter_mut().flatten().zip(data.chunks_exact(4)) {
+            match Action::decode([code[0], code[1], code[2], code[3]]) {
+                Some(decoded) => *action = decoded,
+                None => return false,
+            }
+        }
+        self.layers = layers;
+        true
+    }
+    pub fn load<I2C, E>(&mut self, eeprom: &mut Eeprom<I2C>) -> Result<(), E>
+    where
+        I2C: Write<Error = E> + WriteRead<Error = E>,
+    {
+        let mut data: Vec<u8, { KEYMAP_SIZE - BLOB_HEADER_LEN }> = Vec::new();
+        eeprom.read_blob(KEYMAP_ADDRESS, MAGIC, &mut data)?;
+        //keeps the compiled in keymap if there is none stored
+        self.set_encoded(&data);
+        Ok(())
+    }
+    pub fn save<I2C, E>(&self, eeprom: &mut Eeprom<I2C>) -> Result<(), E>
+    where
+        I2C: Write<Error = E> + WriteRead<Error = E>,
+    {
+        let mut data: Vec<u8, { KEYMAP_SIZE - BLOB_HEADER_LEN }> = Vec::new();
+        data.extend(self.encoded());
+        eeprom.write_blob(KEYMAP_ADDRESS, MAGIC, &data)
+    }
+}
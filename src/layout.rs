@@ -0,0 +1,45 @@
+use crate::keyboard_layouts::{LAYOUT, MATRIX_COLS, MATRIX_ROWS};
+
+//scan indices of one half, the right half has the ones from 0 and the left one the ones after
+//it, no matter which of them is plugged into the host
+pub const HALF_KEYS: usize = MATRIX_ROWS * MATRIX_COLS;
+
+//A key of the board, where it is wired into the matrix and where it sits. The keymap, combos
+//and everything after the scan number the keys by their place in LAYOUT, the matrices only
+//know the scan index.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PhysicalKey {
+    //0 is the right half, 1 the left one of a split board
+    pub half: u8,
+    //driven pin
+    pub row: u8,
+    //read pin
+    pub col: u8,
+    //top left corner in quarter key widths
+    pub x: u8,
+    pub y: u8,
+}
+
+impl PhysicalKey {
+    //index of the key bits in the key buffer, a matrix with offset half * HALF_KEYS puts
+    //row * MATRIX_COLS + col there
+    pub const fn scan_index(&self) -> usize {
+        (self.half as usize * MATRIX_ROWS + self.row as usize) * MATRIX_COLS + self.col as usize
+    }
+}
+
+//key of a scan index, None for a spot of the matrix the layout has no key for
+pub fn key_at(scan_index: usize) -> Option<usize> {
+    LAYOUT
+        .iter()
+        .position(|physical| physical.scan_index() == scan_index)
+}
+
+//half, row and column of a scan index
+pub fn matrix_position(scan_index: usize) -> (u8, u8, u8) {
+    (
+        (scan_index / HALF_KEYS) as u8,
+        (scan_index % HALF_KEYS / MATRIX_COLS) as u8,
+        (scan_index % MATRIX_COLS) as u8,
+    )
+}
@@ -0,0 +1,64 @@
+use crate::keycodes::{KeyCode, KeyCode::*};
+
+//Layout typed on a host set to US QWERTY, the keymap keeps the QWERTY positions and the keys
+//get swapped for the ones the host needs to get the characters of the layout
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EmulatedLayout {
+    Off,
+    Dvorak,
+    Colemak,
+    Workman,
+}
+
+#[rustfmt::skip]
+const DVORAK: [(KeyCode, KeyCode); 33] = [
+    (Minus, LeftBracket), (Equal, RightBracket),
+    (Q, Quote), (W, Comma), (E, Dot), (R, P), (T, Y), (Y, F), (U, G), (I, C), (O, R), (P, L),
+    (LeftBracket, Slash), (RightBracket, Equal),
+    (S, O), (D, E), (F, U), (G, I), (H, D), (J, H), (K, T), (L, N), (Semicolon, S),
+    (Quote, Minus),
+    (Z, Semicolon), (X, Q), (C, J), (V, K), (B, X), (N, B), (Comma, W), (Dot, V), (Slash, Z),
+];
+#[rustfmt::skip]
+const COLEMAK: [(KeyCode, KeyCode); 17] = [
+    (E, F), (R, P), (T, G), (Y, J), (U, L), (I, U), (O, Y), (P, Semicolon),
+    (S, R), (D, S), (F, T), (G, D), (J, N), (K, E), (L, I), (Semicolon, O),
+    (N, K),
+];
+#[rustfmt::skip]
+const WORKMAN: [(KeyCode, KeyCode); 21] = [
+    (W, D), (E, R), (R, W), (T, B), (Y, J), (U, F), (I, U), (O, P), (P, Semicolon),
+    (D, H), (F, T), (H, Y), (J, N), (K, E), (L, O), (Semicolon, I),
+    (C, M), (V, C), (B, V), (N, K), (M, L),
+];
+
+impl EmulatedLayout {
+    pub const ALL: [Self; 4] = [Self::Off, Self::Dvorak, Self::Colemak, Self::Workman];
+
+    pub fn from_u8(layout: u8) -> Option<Self> {
+        Self::ALL.get(layout as usize).copied()
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "QWERTY",
+            Self::Dvorak => "DVORAK",
+            Self::Colemak => "COLEMAK",
+            Self::Workman => "WORKMAN",
+        }
+    }
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+    pub fn remap(self, code: KeyCode) -> KeyCode {
+        let table: &[(KeyCode, KeyCode)] = match self {
+            Self::Off => &[],
+            Self::Dvorak => &DVORAK,
+            Self::Colemak => &COLEMAK,
+            Self::Workman => &WORKMAN,
+        };
+        table
+            .iter()
+            .find(|(qwerty, _)| *qwerty == code)
+            .map_or(code, |(_, host)| *host)
+    }
+}
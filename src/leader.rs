@@ -0,0 +1,104 @@
+use crate::host_layouts::HostLayout;
+use crate::keyboard_layouts::Action;
+use crate::keycodes::{KeyCode, Modifiers};
+use heapless::{String, Vec};
+
+pub const MAX_LEADER_KEYS: usize = 5;
+
+pub struct LeaderSequence {
+    pub keys: &'static [KeyCode],
+    //tapped once the sequence is complete
+    pub action: Action,
+}
+
+#[derive(Clone, Copy)]
+pub struct LeaderConfig {
+    pub timeout: u16,
+    //the timeout starts again with every key instead of counting for the whole sequence
+    pub per_key: bool,
+}
+
+pub enum LeaderResult {
+    Pending,
+    Done(Action),
+    //no sequence starts like this, the leader is over
+    Failed,
+}
+
+//Collects the keys typed after the leader key until they match a sequence
+pub struct Leader {
+    keys: Vec<KeyCode, MAX_LEADER_KEYS>,
+    active: bool,
+    since: u32,
+}
+
+impl Leader {
+    pub fn new() -> Self {
+        Self {
+            keys: Vec::new(),
+            active: false,
+            since: 0,
+        }
+    }
+    pub fn start(&mut self, now: u32) {
+        self.keys.clear();
+        self.active = true;
+        self.since = now;
+    }
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+    pub fn key(
+        &mut self,
+        code: KeyCode,
+        now: u32,
+        sequences: &[LeaderSequence],
+        config: LeaderConfig,
+    ) -> LeaderResult {
+        if self.keys.push(code).is_err() {
+            self.active = false;
+            return LeaderResult::Failed;
+        }
+        if config.per_key {
+            self.since = now;
+        }
+        let keys = &self.keys;
+        let mut candidates = sequences.iter().filter(|s| s.keys.starts_with(keys));
+        match (candidates.next(), candidates.next()) {
+            (None, _) => {
+                self.active = false;
+                LeaderResult::Failed
+            }
+            //a longer sequence could still follow an exact match, that waits for the timeout
+            (Some(sequence), None) if sequence.keys == keys.as_slice() => {
+                self.active = false;
+                LeaderResult::Done(sequence.action)
+            }
+            _ => LeaderResult::Pending,
+        }
+    }
+    //ends the leader once the time is up, returns the action if the keys so far match one
+    pub fn poll(
+        &mut self,
+        now: u32,
+        sequences: &[LeaderSequence],
+        config: LeaderConfig,
+    ) -> Option<Action> {
+        if !self.active || now.wrapping_sub(self.since) < config.timeout as u32 {
+            return None;
+        }
+        self.active = false;
+        sequences
+            .iter()
+            .find(|s| s.keys == self.keys.as_slice())
+            .map(|s| s.action)
+    }
+    //keys typed so far as the host shows them
+    pub fn text(&self, layout: HostLayout) -> String<{ MAX_LEADER_KEYS * 4 }> {
+        let mut text = String::new();
+        for key in &self.keys {
+            let _ = text.push(layout.character(*key, Modifiers::NONE).unwrap_or('?'));
+        }
+        text
+    }
+}
@@ -0,0 +1,395 @@
+use crate::eeprom::{Eeprom, BLOB_HEADER_LEN, MACRO_ADDRESS, MACRO_SIZE};
+use crate::keycodes::{KeyCode, Modifiers};
+use crate::settings::Profile;
+use crate::text_expansion::MAX_EXPANSION;
+use crate::unicode::{input_sequence, InputSequence};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use heapless::{String, Vec};
+
+const MAGIC: [u8; 2] = *b"MC";
+//keys a macro can hold down at once, further presses get released right away
+const MAX_HELD: usize = 6;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum MacroStep<'a> {
+    Press(KeyCode),
+    Release(KeyCode),
+    Tap(KeyCode),
+    //pause playback for n milliseconds
+    Delay(u16),
+    //typed according to the host layout, other characters go through the unicode mode
+    Text(&'a str),
+    Unicode(char),
+}
+
+//Where the steps of the running macro come from, stored macros are a byte range of the
+//MacroStore
+#[derive(Clone, Copy)]
+pub enum MacroSource {
+    Builtin(&'static [MacroStep<'static>]),
+    Stored(usize, usize),
+}
+
+//Macros uploaded by the host, kept as [id][length u16][encoded steps]
+pub struct MacroStore {
+    data: Vec<u8, { MACRO_SIZE - BLOB_HEADER_LEN }>,
+}
+
+pub enum MacroOutput {
+    //key press together with the modifiers the macro wants active
+    Press(KeyCode, Modifiers),
+    Release(KeyCode),
+}
+
+//Plays back a macro one key event at a time so that the caller can hold off
+//whenever the transmit buffer is running full
+pub struct MacroPlayer {
+    source: MacroSource,
+    //step index for builtin macros, byte offset into the store for stored ones
+    position: usize,
+    wait_until: u32,
+    tap_release: Option<KeyCode>,
+    modifiers: Modifiers,
+    held: Vec<KeyCode, MAX_HELD>,
+    //byte offset of the current character of a Text step
+    text_offset: usize,
+    //keys of the character being typed, tapped one after another
+    strokes: InputSequence,
+    stroke_index: usize,
+    //text expansion, typed after the backspaces and before the steps
+    backspaces: usize,
+    expansion: String<MAX_EXPANSION>,
+    expansion_offset: usize,
+}
+
+impl MacroPlayer {
+    pub fn new() -> Self {
+        Self {
+            source: MacroSource::Builtin(&[]),
+            position: 0,
+            wait_until: 0,
+            tap_release: None,
+            modifiers: Modifiers::NONE,
+            held: Vec::new(),
+            text_offset: 0,
+            strokes: InputSequence::new(),
+            stroke_index: 0,
+            backspaces: 0,
+            expansion: String::new(),
+            expansion_offset: 0,
+        }
+    }
+    pub fn start(&mut self, source: MacroSource, now: u32) {
+        self.source = source;
+        self.position = match source {
+            MacroSource::Builtin(_) => 0,
+            MacroSource::Stored(start, _) => start,
+        };
+        self.wait_until = now;
+        self.tap_release = None;
+        self.modifiers = Modifiers::NONE;
+        self.held.clear();
+        self.text_offset = 0;
+        self.strokes.clear();
+        self.stroke_index = 0;
+        self.backspaces = 0;
+        self.expansion.clear();
+        self.expansion_offset = 0;
+    }
+    //deletes the abbreviation and types its expansion
+    pub fn start_expansion(&mut self, backspaces: usize, expansion: &str, now: u32) {
+        self.start(MacroSource::Builtin(&[]), now);
+        self.backspaces = backspaces;
+        let _ = self.expansion.push_str(expansion);
+    }
+    //types a single character outside of a macro
+    pub fn start_char(&mut self, c: char, profile: &Profile) {
+        self.start(MacroSource::Builtin(&[]), 0);
+        self.load_char(c, profile);
+    }
+    //skips the remaining steps, keys still held by the macro get released
+    pub fn cancel(&mut self) {
+        self.position = self.end();
+        self.modifiers = Modifiers::NONE;
+        self.strokes.clear();
+        self.stroke_index = 0;
+        self.backspaces = 0;
+        self.expansion.clear();
+        self.expansion_offset = 0;
+    }
+    pub fn is_playing(&self) -> bool {
+        self.position < self.end()
+            || self.stroke_index < self.strokes.len()
+            || self.backspaces > 0
+            || self.expansion_offset < self.expansion.len()
+            || self.tap_release.is_some()
+            || !self.held.is_empty()
+    }
+    fn end(&self) -> usize {
+        match self.source {
+            MacroSource::Builtin(steps) => steps.len(),
+            MacroSource::Stored(_, end) => end,
+        }
+    }
+    //current step and the position of the one after it
+    fn step<'a>(&self, store: &'a MacroStore) -> Option<(MacroStep<'a>, usize)> {
+        match self.source {
+            MacroSource::Builtin(steps) => {
+                steps.get(self.position).map(|s| (*s, self.position + 1))
+            }
+            MacroSource::Stored(_, end) => {
+                let (step, len) = decode_step(store.data.get(self.position..end)?)?;
+                Some((step, self.position + len))
+            }
+        }
+    }
+    fn load_char(&mut self, c: char, profile: &Profile) {
+        self.strokes.clear();
+        self.stroke_index = 0;
+        match profile.host_layout.lookup(c) {
+            Some((dead, stroke)) => {
+                if let Some(dead) = dead {
+                    let _ = self.strokes.push(dead);
+                }
+                let _ = self.strokes.push(stroke);
+            }
+            None => self.strokes = input_sequence(profile.unicode_mode, profile.host_layout, c),
+        }
+    }
+    //returns the next key event without consuming it, call commit() once it has been sent
+    pub fn next_output(
+        &mut self,
+        now: u32,
+        profile: &Profile,
+        store: &MacroStore,
+    ) -> Option<MacroOutput> {
+        loop {
+            if let Some(key) = self.tap_release {
+                return Some(MacroOutput::Release(key));
+            }
+            if let Some(stroke) = self.strokes.get(self.stroke_index) {
+                return Some(MacroOutput::Press(
+                    stroke.key,
+                    stroke.modifiers | self.modifiers,
+                ));
+            }
+            if self.backspaces > 0 {
+                return Some(MacroOutput::Press(KeyCode::Backspace, self.modifiers));
+            }
+            if let Some(c) = self.expansion[self.expansion_offset..].chars().next() {
+                self.expansion_offset += c.len_utf8();
+                self.load_char(c, profile);
+                continue;
+            }
+            let (step, next) = match self.step(store) {
+                Some(step) => step,
+                None => {
+                    //finished, or a stored macro ends in garbage
+                    self.position = self.end();
+                    self.modifiers = Modifiers::NONE;
+                    return self.held.last().map(|key| MacroOutput::Release(*key));
+                }
+            };
+            if (now.wrapping_sub(self.wait_until) as i32) < 0 {
+                return None;
+            }
+            match step {
+                MacroStep::Delay(ms) => {
+                    self.wait_until = now.wrapping_add(ms as u32);
+                }
+                MacroStep::Press(key) if key.is_modifier() => {
+                    self.modifiers = self.modifiers | key.modifier();
+                }
+                MacroStep::Release(key) if key.is_modifier() => {
+                    self.modifiers = self.modifiers & !key.modifier();
+                }
+                MacroStep::Press(key) | MacroStep::Tap(key) => {
+                    return Some(MacroOutput::Press(key, self.modifiers));
+                }
+                MacroStep::Release(key) => {
+                    return Some(MacroOutput::Release(key));
+                }
+                MacroStep::Text(text) => {
+                    if let Some(c) = text[self.text_offset..].chars().next() {
+                        self.text_offset += c.len_utf8();
+                        self.load_char(c, profile);
+                        continue;
+                    }
+                    self.text_offset = 0;
+                }
+                MacroStep::Unicode(c) => {
+                    self.strokes = input_sequence(profile.unicode_mode, profile.host_layout, c);
+                    self.stroke_index = 0;
+                }
+            }
+            self.position = next;
+        }
+    }
+    pub fn commit(&mut self, store: &MacroStore) {
+        if self.tap_release.take().is_some() {
+            return;
+        }
+        if let Some(stroke) = self.strokes.get(self.stroke_index) {
+            self.tap_release = Some(stroke.key);
+            self.stroke_index += 1;
+            return;
+        }
+        if self.backspaces > 0 {
+            self.tap_release = Some(KeyCode::Backspace);
+            self.backspaces -= 1;
+            return;
+        }
+        let (step, next) = match self.step(store) {
+            Some(step) => step,
+            None => {
+                self.held.pop();
+                return;
+            }
+        };
+        match step {
+            MacroStep::Press(key) => {
+                //a key the macro can't keep track of would stay stuck on the host
+                if self.held.push(key).is_err() {
+                    self.tap_release = Some(key);
+                }
+            }
+            MacroStep::Tap(key) => {
+                self.tap_release = Some(key);
+            }
+            MacroStep::Release(key) => {
+                self.held.retain(|k| *k != key);
+            }
+            MacroStep::Delay(_) | MacroStep::Text(_) | MacroStep::Unicode(_) => {}
+        }
+        self.position = next;
+    }
+}
+
+impl MacroStore {
+    pub fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+    pub fn find(&self, id: u8) -> Option<MacroSource> {
+        let mut offset = 0;
+        while let Some(len) = entry_len(&self.data[offset..]) {
+            if self.data[offset] == id {
+                return Some(MacroSource::Stored(offset + 3, offset + len));
+            }
+            offset += len;
+        }
+        None
+    }
+    pub fn raw(&self) -> &[u8] {
+        &self.data
+    }
+    //only takes a store with steps that decode completely
+    pub fn set_raw(&mut self, data: &[u8]) -> bool {
+        if !is_valid(data) {
+            return false;
+        }
+        self.data.clear();
+        self.data.extend_from_slice(data).is_ok()
+    }
+    pub fn load<I2C, E>(&mut self, eeprom: &mut Eeprom<I2C>) -> Result<(), E>
+    where
+        I2C: Write<Error = E> + WriteRead<Error = E>,
+    {
+        eeprom.read_blob(MACRO_ADDRESS, MAGIC, &mut self.data)?;
+        if !is_valid(&self.data) {
+            self.data.clear();
+        }
+        Ok(())
+    }
+    pub fn save<I2C, E>(&self, eeprom: &mut Eeprom<I2C>) -> Result<(), E>
+    where
+        I2C: Write<Error = E> + WriteRead<Error = E>,
+    {
+        eeprom.write_blob(MACRO_ADDRESS, MAGIC, &self.data)
+    }
+}
+
+fn entry_len(data: &[u8]) -> Option<usize> {
+    let len = 3 + u16::from_le_bytes([*data.get(1)?, *data.get(2)?]) as usize;
+    (data.len() >= len).then_some(len)
+}
+fn is_valid(mut data: &[u8]) -> bool {
+    while !data.is_empty() {
+        let len = match entry_len(data) {
+            Some(len) => len,
+            None => return false,
+        };
+        let mut steps = &data[3..len];
+        while !steps.is_empty() {
+            match decode_step(steps) {
+                Some((_, step_len)) => steps = &steps[step_len..],
+                None => return false,
+            }
+        }
+        data = &data[len..];
+    }
+    true
+}
+
+//Step encoding used by the stored macros, returns the step and its encoded length
+pub fn decode_step(data: &[u8]) -> Option<(MacroStep<'_>, usize)> {
+    let key = || data.get(1).and_then(|code| KeyCode::from_u8(*code));
+    match *data.first()? {
+        0x01 => Some((MacroStep::Press(key()?), 2)),
+        0x02 => Some((MacroStep::Release(key()?), 2)),
+        0x03 => Some((MacroStep::Tap(key()?), 2)),
+        0x04 => Some((
+            MacroStep::Delay(u16::from_le_bytes([*data.get(1)?, *data.get(2)?])),
+            3,
+        )),
+        0x05 => {
+            let len = *data.get(1)? as usize;
+            let text = core::str::from_utf8(data.get(2..2 + len)?).ok()?;
+            Some((MacroStep::Text(text), 2 + len))
+        }
+        0x06 => {
+            let code = u32::from_le_bytes([*data.get(1)?, *data.get(2)?, *data.get(3)?, 0]);
+            Some((MacroStep::Unicode(char::from_u32(code)?), 4))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEPS: [MacroStep; 5] = [
+        MacroStep::Press(KeyCode::LShift),
+        MacroStep::Press(KeyCode::A),
+        MacroStep::Press(KeyCode::B),
+        MacroStep::Delay(1000),
+        MacroStep::Tap(KeyCode::C),
+    ];
+
+    #[test]
+    fn cancel_leaves_the_releases() {
+        let (profile, store) = (Profile::new(), MacroStore::new());
+        let mut player = MacroPlayer::new();
+        player.start(MacroSource::Builtin(&STEPS), 0);
+        for key in [KeyCode::A, KeyCode::B] {
+            match player.next_output(0, &profile, &store) {
+                Some(MacroOutput::Press(code, Modifiers::LSHIFT)) => assert_eq!(code, key),
+                _ => panic!("{:?} not pressed", key),
+            }
+            player.commit(&store);
+        }
+        //waiting on the delay
+        assert!(player.next_output(0, &profile, &store).is_none());
+        player.cancel();
+        for key in [KeyCode::B, KeyCode::A] {
+            match player.next_output(0, &profile, &store) {
+                Some(MacroOutput::Release(code)) => assert_eq!(code, key),
+                _ => panic!("{:?} not released", key),
+            }
+            player.commit(&store);
+        }
+        assert!(player.next_output(0, &profile, &store).is_none());
+        assert!(!player.is_playing());
+    }
+}
@@ -1,6 +1,7 @@
 #![no_std]
 #![no_main]
 
+use core::cell::RefCell;
 use core::num::Wrapping;
 use embedded_graphics::prelude::Point;
 use embedded_graphics::prelude::Size;
@@ -14,7 +15,7 @@ use gd32vf103xx_hal::{
     eclic::{EclicExt, Level, LevelPriorityBits, Priority, TriggerType},
     gpio::{
         gpiob::{PB0, PB1},
-        Input, OpenDrain, Output, PullDown, PushPull, Pxx,
+        OpenDrain, Output, PullDown,
     },
     i2c::*,
     pac,
@@ -23,17 +24,70 @@ use gd32vf103xx_hal::{
     timer::{Event, Timer},
 };
 //use ringbuffer::ConstGenericRingBuffer;
+#[cfg(feature = "split")]
+use core::convert::Infallible;
+#[cfg(feature = "split")]
+use gd32vf103xx_hal::{
+    pac::USART1,
+    serial::{Config, Rx, Serial, Tx},
+};
+#[cfg(feature = "split")]
+use heapless::spsc::Queue;
 use sh1106::{prelude::*, Builder};
 
+mod auto_shift;
+mod combos;
+mod crc;
+mod eeprom;
+mod eeprom_map;
+//expander.rs, key_matrix.rs and shift_register.rs scan the matrices of other boards, behind an
+//I/O expander, on pins of any port or behind 74HC595/74HC165 chains. This one doesn't build
+//them but tools/firmware_tests does and tests them.
 #[macro_use]
 mod gui;
+//only the hand setting is used without the split link
+#[cfg_attr(not(feature = "split"), allow(dead_code))]
+mod handedness;
+mod host_layouts;
+mod i2c_bus;
+mod key_overrides;
 mod keyboard;
 mod keyboard_layouts;
+mod keycodes;
+mod keymap;
+mod layout;
+mod layout_emulation;
+mod leader;
+mod macros;
+mod matrix_test;
+mod menu;
+mod one_shot;
 mod pin_defs;
+mod port_matrix;
 mod ps2;
+mod scancodes;
+mod settings;
+#[cfg(feature = "split")]
+mod split;
 mod stdout;
+mod storage;
+mod tap_hold;
+mod text_expansion;
+mod unicode;
+mod vendor;
+mod word_modes;
+use eeprom::Eeprom;
+#[cfg(feature = "split")]
+use handedness::Hand;
+use i2c_bus::I2cProxy;
 use keyboard::*;
+use keyboard_layouts::{MATRIX_COLS, MATRIX_ROWS};
+#[cfg(feature = "split")]
+use layout::HALF_KEYS;
 use pin_defs::*;
+#[cfg(feature = "split")]
+use riscv::asm::delay;
+use settings::Settings;
 
 static mut G_TIMER2: Option<Timer<TIMER2>> = None;
 static mut G_TIMER1: Option<Timer<TIMER1>> = None;
@@ -42,13 +96,27 @@ static mut G_TIMER3: Option<Timer<TIMER3>> = None;
 static mut TIME: u32 = 0;
 //LEDPWM
 static mut LED_PWM: Option<LedPwm> = None;
-type KB = Keyboard<
-    keyboard::KeyMatrix<Pxx<Output<PushPull>>, Pxx<Input<PullDown>>, 7_usize, 7_usize>,
-    1_usize,
-    PB0<Output<OpenDrain>>,
-    PB1<Output<OpenDrain>>,
->;
+type HalfMatrix = port_matrix::PortMatrix<PullDown, MATRIX_ROWS, MATRIX_COLS>;
+#[cfg(not(feature = "split"))]
+type Matrix = HalfMatrix;
+//the keys of the other half come in over the link
+#[cfg(feature = "split")]
+type Matrix = (
+    HalfMatrix,
+    split::SplitMatrix<SplitRx, Tx<USART1>, HALF_KEYS>,
+);
+type KB = Keyboard<Matrix, 1_usize, PB0<Output<OpenDrain>>, PB1<Output<OpenDrain>>>;
 static mut KEYBOARD: Option<KB> = None;
+//the half without a host only scans and sends its keys
+#[cfg(feature = "split")]
+static mut SECONDARY: Option<split::SecondaryHalf<HalfMatrix, SplitRx, Tx<USART1>, HALF_KEYS>> =
+    None;
+//the scan only runs every ms, the USART1 interrupt takes the bytes of the other half off the
+//port before the next one overwrites them
+#[cfg(feature = "split")]
+static mut SPLIT_RX: Option<Rx<USART1>> = None;
+#[cfg(feature = "split")]
+static mut SPLIT_BUFFER: Queue<u8, 64> = Queue::new();
 //Time overflow after ~119,3h
 #[allow(dead_code)]
 fn get_millis() -> u32 {
@@ -89,21 +157,6 @@ fn main() -> ! {
     unsafe {
         LED_PWM = Some(LedPwm::new(gpiob.pb8, 220));
     }
-    unsafe {
-        let right_kb = KeyMatrix::new(
-            pp_output!(gpioa.pa0, gpioa.pa1, gpioa.pa2, gpioa.pa3, gpioa.pa4, gpioa.pa5, gpioa.pa6),
-            pd_input!(
-                gpiob.pb9, gpiob.pb10, gpiob.pb11, gpiob.pb12, gpiob.pb13, gpiob.pb14, gpiob.pb15
-            ),
-            0,
-        );
-        KEYBOARD = Some(Keyboard::new(
-            [right_kb],
-            gpiob.pb0.into_open_drain_output(),
-            gpiob.pb1.into_open_drain_output(),
-        ));
-    }
-
     crate::stdout::configure(
         dp.USART0,
         gpioa.pa9,
@@ -128,11 +181,103 @@ fn main() -> ! {
         998,
         998,
     );
+    //display and EEPROM share the bus
+    let i2c_bus = RefCell::new(i2c);
+    let mut eeprom = Eeprom::new(I2cProxy(&i2c_bus), 0x50);
+    let mut settings = Settings::new();
+    let mut eeprom_ok = settings.load(&mut eeprom).is_ok();
+    //The half the host is plugged into is the primary, the hand comes from the strap pin PB5
+    //or the settings. The pins get their final mode afterwards.
+    #[cfg(feature = "split")]
+    let (ps2_clock, primary) = {
+        let clock = gpiob.pb1.into_pull_down_input();
+        let primary = handedness::host_present(&clock, 20, || unsafe { delay(108_000) });
+        (clock.into_open_drain_output(), primary)
+    };
+    #[cfg(not(feature = "split"))]
+    let ps2_clock = gpiob.pb1.into_open_drain_output();
+    #[cfg(feature = "split")]
+    let hand = {
+        let strap = gpiob.pb5.into_pull_down_input();
+        unsafe { delay(1_000) };
+        let high_pulled_down =
+            embedded_hal::digital::v2::InputPin::is_high(&strap).unwrap_or(false);
+        let strap = strap.into_pull_up_input();
+        unsafe { delay(1_000) };
+        let high_pulled_up = embedded_hal::digital::v2::InputPin::is_high(&strap).unwrap_or(true);
+        Hand::from_strap(high_pulled_down, high_pulled_up)
+            .or(settings.hand)
+            .unwrap_or(Hand::Right)
+    };
+    #[cfg(feature = "split")]
+    sprintln!(
+        "{} half, {}",
+        hand.name(),
+        if primary { "primary" } else { "secondary" }
+    );
+    #[cfg(feature = "split")]
+    let split_tx = {
+        let (tx, mut rx) = Serial::new(
+            dp.USART1,
+            (gpioa.pa2, gpioa.pa3),
+            Config::default().baudrate(115_200.bps()),
+            &mut afio,
+            &mut rcu,
+        )
+        .split();
+        rx.listen();
+        unsafe { SPLIT_RX = Some(rx) };
+        tx
+    };
+    unsafe {
+        #[cfg(not(feature = "split"))]
+        let address =
+            pp_output!(gpioa.pa0, gpioa.pa1, gpioa.pa2, gpioa.pa3, gpioa.pa4, gpioa.pa5, gpioa.pa6);
+        //USART1 sits on PA2 and PA3, split boards wire those rows to PA7 and PA8
+        #[cfg(feature = "split")]
+        let address =
+            pp_output!(gpioa.pa0, gpioa.pa1, gpioa.pa7, gpioa.pa8, gpioa.pa4, gpioa.pa5, gpioa.pa6);
+        //the secondary sends its keys from 0, the primary puts them at the offset of their hand
+        #[cfg(feature = "split")]
+        let offset = if primary { hand.offset() } else { 0 };
+        #[cfg(not(feature = "split"))]
+        let offset = 0;
+        let half = port_matrix::PortMatrix::new(
+            address,
+            pd_input!(
+                gpiob.pb9, gpiob.pb10, gpiob.pb11, gpiob.pb12, gpiob.pb13, gpiob.pb14, gpiob.pb15
+            ),
+            offset,
+        );
+        let ps2_data = gpiob.pb0.into_open_drain_output();
+        #[cfg(feature = "split")]
+        if primary {
+            let other = split::SplitMatrix::new(SplitRx, split_tx, hand.other().offset());
+            KEYBOARD = Some(Keyboard::new([(half, other)], ps2_data, ps2_clock));
+        } else {
+            SECONDARY = Some(split::SecondaryHalf::new(
+                half,
+                split::SplitSender::new(SplitRx, split_tx),
+            ));
+        }
+        #[cfg(not(feature = "split"))]
+        {
+            KEYBOARD = Some(Keyboard::new([half], ps2_data, ps2_clock));
+        }
+        if let Some(keyboard) = KEYBOARD.as_mut() {
+            *keyboard.settings_mut() = settings;
+            eeprom_ok &= keyboard.storage_mut().load(&mut eeprom).is_ok();
+        }
+    }
+    if !eeprom_ok {
+        sprintln!("EEPROM not responding");
+    }
+
     /*Display*/
     let mut disp: GraphicsMode<_> = Builder::new()
         .with_size(DisplaySize::Display128x64)
         .with_rotation(DisplayRotation::Rotate180)
-        .connect_i2c(i2c)
+        .connect_i2c(I2cProxy(&i2c_bus))
         .into();
 
     disp.init().unwrap();
@@ -149,12 +294,146 @@ fn main() -> ! {
     let _static_gui_elem = [tab1, tab2, tab3];
     unsafe { riscv::interrupt::enable() };
     tm4.start(1.khz());
+    #[cfg(feature = "split")]
+    if !primary {
+        loop {
+            unsafe {
+                //the link runs in the scan interrupt
+                let (changed, status, lines) = riscv::interrupt::free(|_| {
+                    let sender = SECONDARY.as_mut().unwrap().sender_mut();
+                    (
+                        sender.take_changed(),
+                        sender.status(),
+                        sender.lines().clone(),
+                    )
+                });
+                if changed {
+                    LED_PWM.as_mut().unwrap().set_threshold(status.brightness);
+                    gui::draw_secondary(&mut disp, &status, &lines);
+                }
+            }
+            block!(tm4.wait()).unwrap();
+        }
+    }
     let mut last = get_millis();
+    let mut shown_one_shots = None;
+    let mut shown_leader = None;
+    let mut shown_menu = None;
     loop {
         //draw_gui(&mut disp, &static_gui_elem);
         let start = get_millis();
         unsafe {
-            KEYBOARD.as_mut().unwrap().process_keystrokes();
+            let keyboard = KEYBOARD.as_mut().unwrap();
+            keyboard.process_keystrokes();
+            //uploads from the host only get saved here, outside the timing critical parts
+            if keyboard.storage_mut().save_pending(&mut eeprom).is_err()
+                || keyboard.save_settings(&mut eeprom).is_err()
+            {
+                sprintln!("EEPROM write failed");
+            }
+            //the key tester takes the display and sends "matrix <ms> <half> <row> <col> <key or
+            //-1> <pressed> <chatter>" for every change it saw
+            let (testing, test_changed) = riscv::interrupt::free(|_| {
+                let test = keyboard.matrix_test_mut();
+                (test.is_active(), test.take_changed())
+            });
+            while let Some((event, chatter)) = riscv::interrupt::free(|_| {
+                let test = keyboard.matrix_test_mut();
+                test.next_event()
+                    .map(|event| (event, test.chatter(event.scan_index as usize)))
+            }) {
+                let (half, row, col) = layout::matrix_position(event.scan_index as usize);
+                sprintln!(
+                    "matrix {} {} {} {} {} {} {}",
+                    event.time,
+                    half,
+                    row,
+                    col,
+                    layout::key_at(event.scan_index as usize).map_or(-1, |key| key as i32),
+                    event.pressed as u8,
+                    chatter
+                );
+            }
+            if testing {
+                if test_changed {
+                    gui::draw_matrix_test(&mut disp, keyboard.matrix_test());
+                }
+                //everything gets drawn again after the test
+                shown_one_shots = None;
+                shown_leader = None;
+                shown_menu = None;
+                block!(tm4.wait()).unwrap();
+                continue;
+            }
+            if test_changed {
+                disp.clear();
+            }
+            //the link to the other half, a board without one is always complete
+            #[cfg(feature = "split")]
+            let linked = riscv::interrupt::free(|_| keyboard.matricies_mut()[0].1.is_connected());
+            #[cfg(not(feature = "split"))]
+            let linked = true;
+            //the display only gets redrawn when the one-shots or the indicators change, a flush
+            //takes a while
+            let caps_word = keyboard.caps_word_active();
+            let (modifiers, layers) = (keyboard.one_shot_modifiers(), keyboard.one_shot_layers());
+            let one_shots = Some((
+                caps_word,
+                linked,
+                [
+                    modifiers.pending(),
+                    modifiers.locked(),
+                    layers.pending(),
+                    layers.locked(),
+                ],
+            ));
+            if one_shots != shown_one_shots {
+                let mut indicators: String<16> = String::new();
+                if caps_word {
+                    let _ = indicators.push_str("CW ");
+                }
+                if !linked {
+                    let _ = indicators.push_str("NO LINK ");
+                }
+                gui::draw_one_shots(&mut disp, &indicators, modifiers, layers);
+                shown_one_shots = one_shots;
+            }
+            let (menu, settings) = (keyboard.menu(), keyboard.settings());
+            let shown = Some((
+                menu.is_open(),
+                menu.selected(),
+                settings.emulated_layout,
+                settings.shortcut_passthrough,
+                settings.active_profile,
+                *settings.profile(),
+                settings.hand,
+                settings.auto_shift,
+            ));
+            if shown != shown_menu {
+                gui::draw_menu(&mut disp, menu, settings);
+                shown_menu = shown;
+            }
+            let leader = keyboard.leader_text();
+            #[cfg(feature = "split")]
+            {
+                let status = split::HalfStatus {
+                    layers: keyboard.layers(),
+                    brightness: LED_PWM.as_ref().unwrap().threshold(),
+                    host_leds: keyboard.host_leds(),
+                };
+                let layout = settings.emulated_layout.name();
+                let leader = leader.as_deref().unwrap_or("");
+                riscv::interrupt::free(|_| {
+                    let link = &mut keyboard.matricies_mut()[0].1;
+                    link.set_status(status);
+                    link.set_line(0, layout);
+                    link.set_line(1, leader);
+                });
+            }
+            if leader != shown_leader {
+                gui::draw_leader(&mut disp, leader.as_deref());
+                shown_leader = leader;
+            }
         }
         if last + 1_000 <= get_millis() {
             sprintln!("Processing Time:{}", get_millis() - start);
@@ -190,10 +469,22 @@ fn setup_interrupts() {
         Level::L0,
         Priority::P1,
     );
+    //a higher level so it gets the bytes while the scan runs
+    #[cfg(feature = "split")]
+    ECLIC::setup(
+        Interrupt::USART1,
+        TriggerType::Level,
+        Level::L1,
+        Priority::P0,
+    );
 
     //  unsafe { ECLIC::unmask(Interrupt::TIMER1) };
     unsafe { ECLIC::unmask(Interrupt::TIMER2) };
     unsafe { ECLIC::unmask(Interrupt::TIMER3) };
+    #[cfg(feature = "split")]
+    unsafe {
+        ECLIC::unmask(Interrupt::USART1)
+    };
 }
 
 #[allow(non_snake_case)]
@@ -203,7 +494,9 @@ fn TIMER2() {
         timer.clear_update_interrupt_flag();
     }
     unsafe {
-        KEYBOARD.as_mut().unwrap().update_interface();
+        if let Some(keyboard) = KEYBOARD.as_mut() {
+            keyboard.update_interface();
+        }
         LED_PWM.as_mut().unwrap().update();
     }
 }
@@ -228,6 +521,36 @@ fn TIMER3() {
     }
     unsafe {
         TIME = (Wrapping(TIME) + Wrapping(1u32)).0;
-        KEYBOARD.as_mut().unwrap().scan();
+        if let Some(keyboard) = KEYBOARD.as_mut() {
+            keyboard.scan();
+        }
+        #[cfg(feature = "split")]
+        if let Some(secondary) = SECONDARY.as_mut() {
+            secondary.scan();
+        }
+    }
+}
+
+#[cfg(feature = "split")]
+#[allow(non_snake_case)]
+#[no_mangle]
+fn USART1() {
+    unsafe {
+        //a byte with a framing or overrun error gets dropped, the crc of its frame fails
+        if let Ok(byte) = SPLIT_RX.as_mut().unwrap().read() {
+            let _ = SPLIT_BUFFER.enqueue(byte);
+        }
+    }
+}
+
+//Receiving end of the split link, reads what the USART1 interrupt buffered
+#[cfg(feature = "split")]
+pub struct SplitRx;
+
+#[cfg(feature = "split")]
+impl embedded_hal::serial::Read<u8> for SplitRx {
+    type Error = Infallible;
+    fn read(&mut self) -> nb::Result<u8, Infallible> {
+        unsafe { SPLIT_BUFFER.dequeue() }.ok_or(nb::Error::WouldBlock)
     }
 }
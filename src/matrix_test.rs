@@ -0,0 +1,143 @@
+use crate::keyboard_layouts::LAYOUT;
+use bitvec::prelude::*;
+use ringbuffer::{ConstGenericRingBuffer, RingBufferRead, RingBufferWrite};
+
+//Key tester for bringing up a board. While it runs the keys only go to the display and the
+//debug console, nothing is sent to the host. It starts with the first two keys of the layout
+//held at boot or from the menu, holding the same keys for EXIT_HOLD ends it once all keys are
+//released again.
+pub const SCAN_KEYS: usize = 128;
+//a press this soon after the release of the same key counts as chatter
+pub const CHATTER_TIME: u32 = 20;
+pub const EXIT_HOLD: u32 = 2000;
+//scans before the keys held at boot get looked at
+pub const BOOT_SCANS: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TestEvent {
+    pub scan_index: u8,
+    pub pressed: bool,
+    pub time: u32,
+}
+
+pub struct MatrixTest {
+    active: bool,
+    leaving: bool,
+    pressed: BitArr!(for SCAN_KEYS),
+    seen: BitArr!(for SCAN_KEYS),
+    chatter: [u8; SCAN_KEYS],
+    released_at: [u32; SCAN_KEYS],
+    //since when the exit keys are held
+    exit_held: Option<u32>,
+    //for the debug console, the oldest ones get dropped if it falls behind
+    events: ConstGenericRingBuffer<TestEvent, 16>,
+    changed: bool,
+}
+
+impl MatrixTest {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            leaving: false,
+            pressed: BitArray::ZERO,
+            seen: BitArray::ZERO,
+            chatter: [0; SCAN_KEYS],
+            released_at: [0; SCAN_KEYS],
+            exit_held: None,
+            events: ConstGenericRingBuffer::new(),
+            changed: false,
+        }
+    }
+    //keys held already count as pressed and seen
+    pub fn start(&mut self, keybuffer: &BitArr!(for 256)) {
+        *self = Self::new();
+        for scan_index in 0..SCAN_KEYS {
+            self.pressed.set(scan_index, keybuffer[scan_index * 2]);
+        }
+        self.seen = self.pressed;
+        self.active = true;
+        self.changed = true;
+    }
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+    //the first two keys of the layout
+    pub fn exit_keys_held(keybuffer: &BitArr!(for 256)) -> bool {
+        LAYOUT
+            .iter()
+            .take(2)
+            .all(|physical| keybuffer[physical.scan_index() * 2])
+    }
+    //called by the scan for every key that changed
+    pub fn event(&mut self, scan_index: usize, pressed: bool, time: u32) {
+        if scan_index >= SCAN_KEYS {
+            return;
+        }
+        if pressed {
+            if self.seen[scan_index]
+                && time.wrapping_sub(self.released_at[scan_index]) < CHATTER_TIME
+            {
+                self.chatter[scan_index] = self.chatter[scan_index].saturating_add(1);
+            }
+            self.seen.set(scan_index, true);
+        } else {
+            self.released_at[scan_index] = time;
+        }
+        self.pressed.set(scan_index, pressed);
+        self.events.push(TestEvent {
+            scan_index: scan_index as u8,
+            pressed,
+            time,
+        });
+        self.changed = true;
+    }
+    //called by the scan after the events
+    pub fn poll(&mut self, keybuffer: &BitArr!(for 256), now: u32) {
+        match self.exit_held {
+            _ if !Self::exit_keys_held(keybuffer) => self.exit_held = None,
+            None => self.exit_held = Some(now),
+            Some(since) if now.wrapping_sub(since) >= EXIT_HOLD && !self.leaving => {
+                self.leaving = true;
+                self.changed = true;
+            }
+            Some(_) => {}
+        }
+        if self.leaving && self.pressed.not_any() {
+            self.active = false;
+            self.changed = true;
+        }
+    }
+    pub fn next_event(&mut self) -> Option<TestEvent> {
+        self.events.dequeue()
+    }
+    pub fn take_changed(&mut self) -> bool {
+        core::mem::replace(&mut self.changed, false)
+    }
+    pub fn is_leaving(&self) -> bool {
+        self.leaving
+    }
+    pub fn is_pressed(&self, scan_index: usize) -> bool {
+        self.pressed[scan_index]
+    }
+    pub fn is_seen(&self, scan_index: usize) -> bool {
+        self.seen[scan_index]
+    }
+    pub fn chatter(&self, scan_index: usize) -> u8 {
+        self.chatter[scan_index]
+    }
+    pub fn pressed(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pressed.iter_ones()
+    }
+    pub fn seen_count(&self) -> usize {
+        self.seen.count_ones()
+    }
+    //the key that chattered the most
+    pub fn worst_chatter(&self) -> Option<(usize, u8)> {
+        self.chatter
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .max_by_key(|(_, count)| **count)
+            .map(|(index, count)| (index, *count))
+    }
+}
@@ -0,0 +1,117 @@
+use crate::handedness::Hand;
+use crate::keycodes::KeyCode;
+use crate::settings::{Settings, PROFILE_COUNT};
+use core::fmt::Write;
+use heapless::String;
+
+pub const MENU_ITEMS: usize = 8;
+
+//Settings menu on the OLED, it takes the typed keys while it is open. Up and Down select,
+//Enter, Space, Left and Right change the selected setting or start the key tester, Escape
+//closes it.
+pub struct Menu {
+    open: bool,
+    selected: u8,
+    //the last entry starts the key tester, the keyboard takes it from here
+    matrix_test: bool,
+}
+
+impl Menu {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            selected: 0,
+            matrix_test: false,
+        }
+    }
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+    pub fn selected(&self) -> u8 {
+        self.selected
+    }
+    pub fn take_matrix_test(&mut self) -> bool {
+        core::mem::replace(&mut self.matrix_test, false)
+    }
+    //returns true if a setting changed
+    pub fn key(&mut self, code: KeyCode, settings: &mut Settings) -> bool {
+        match code {
+            KeyCode::Escape => self.open = false,
+            KeyCode::Up => {
+                self.selected = (self.selected + MENU_ITEMS as u8 - 1) % MENU_ITEMS as u8
+            }
+            KeyCode::Down => self.selected = (self.selected + 1) % MENU_ITEMS as u8,
+            KeyCode::Enter | KeyCode::Space | KeyCode::Left | KeyCode::Right => {
+                match self.selected {
+                    0 => settings.emulated_layout = settings.emulated_layout.next(),
+                    1 => settings.shortcut_passthrough = !settings.shortcut_passthrough,
+                    2 => {
+                        settings.active_profile =
+                            (settings.active_profile + 1) % PROFILE_COUNT as u8
+                    }
+                    3 => {
+                        let profile = settings.profile_mut();
+                        profile.host_layout = profile.host_layout.next();
+                    }
+                    4 => {
+                        let profile = settings.profile_mut();
+                        profile.unicode_mode = profile.unicode_mode.next();
+                    }
+                    5 => {
+                        settings.hand = match settings.hand {
+                            None => Some(Hand::Left),
+                            Some(Hand::Left) => Some(Hand::Right),
+                            Some(Hand::Right) => None,
+                        }
+                    }
+                    6 => settings.auto_shift = !settings.auto_shift,
+                    _ => {
+                        self.matrix_test = true;
+                        self.open = false;
+                        return false;
+                    }
+                }
+                return true;
+            }
+            _ => {}
+        }
+        false
+    }
+    pub fn lines(&self, settings: &Settings) -> [String<21>; MENU_ITEMS] {
+        let mut lines: [String<21>; MENU_ITEMS] = Default::default();
+        let _ = write!(lines[0], "Layout: {}", settings.emulated_layout.name());
+        let _ = write!(
+            lines[1],
+            "Shortcuts: {}",
+            if settings.shortcut_passthrough {
+                "QWERTY"
+            } else {
+                "layout"
+            }
+        );
+        //the host layout and the unicode mode below belong to the profile
+        let _ = write!(lines[2], "Profile: {}", settings.active_profile + 1);
+        let _ = write!(lines[3], "Host: {}", settings.profile().host_layout.name());
+        let _ = write!(
+            lines[4],
+            "Unicode: {}",
+            settings.profile().unicode_mode.name()
+        );
+        //taken at the next start
+        let _ = write!(
+            lines[5],
+            "Hand: {}",
+            settings.hand.map_or("strap pin", Hand::name)
+        );
+        let _ = write!(
+            lines[6],
+            "Auto shift: {}",
+            if settings.auto_shift { "on" } else { "off" }
+        );
+        let _ = lines[7].push_str("Matrix test");
+        lines
+    }
+}
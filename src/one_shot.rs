@@ -0,0 +1,78 @@
+//Sticky keys, a bit per modifier or layer. A tapped one-shot stays active for the next key,
+//tapping it again while it is pending locks it until it is tapped once more. Held down
+//together with other keys it works like the normal modifier or layer.
+pub const ONE_SHOT_TIMEOUT: u32 = 3000;
+
+pub struct OneShot {
+    held: u8,
+    pending: u8,
+    locked: u8,
+    //presses that locked or unlocked, their release does nothing
+    ignore_release: u8,
+    //another key was pressed while one-shots were held
+    interrupted: bool,
+    since: u32,
+}
+
+impl OneShot {
+    pub fn new() -> Self {
+        Self {
+            held: 0,
+            pending: 0,
+            locked: 0,
+            ignore_release: 0,
+            interrupted: false,
+            since: 0,
+        }
+    }
+    pub fn press(&mut self, mask: u8, now: u32) {
+        if self.locked & mask == mask {
+            self.locked &= !mask;
+            self.pending &= !mask;
+            self.ignore_release |= mask;
+        } else if self.pending & mask == mask {
+            self.locked |= mask;
+            self.ignore_release |= mask;
+        } else {
+            if self.held == 0 {
+                self.interrupted = false;
+            }
+            self.held |= mask;
+        }
+        self.since = now;
+    }
+    pub fn release(&mut self, mask: u8, now: u32) {
+        if self.ignore_release & mask != 0 {
+            self.ignore_release &= !mask;
+            return;
+        }
+        self.held &= !mask;
+        if !self.interrupted {
+            self.pending |= mask;
+            self.since = now;
+        }
+    }
+    //another key got pressed, it used up the pending one-shots
+    pub fn key_used(&mut self) {
+        self.interrupted = true;
+        self.pending = self.locked;
+    }
+    //returns true if pending one-shots timed out
+    pub fn poll(&mut self, now: u32) -> bool {
+        if self.pending != self.locked && now.wrapping_sub(self.since) > ONE_SHOT_TIMEOUT {
+            self.pending = self.locked;
+            return true;
+        }
+        false
+    }
+    pub fn active(&self) -> u8 {
+        self.held | self.pending
+    }
+    //waiting for the next key, not counting locked ones
+    pub fn pending(&self) -> u8 {
+        self.pending & !self.locked
+    }
+    pub fn locked(&self) -> u8 {
+        self.locked
+    }
+}
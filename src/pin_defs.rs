@@ -30,7 +30,13 @@ impl LedPwm {
         }
         self.count = (Wrapping(self.count) + Wrapping(1u8)).0;
     }
+    //the secondary half of a split board takes the brightness of the primary
+    #[cfg(feature = "split")]
     pub fn set_threshold(&mut self, thresh: u8) {
         self.thresh = thresh;
     }
+    #[cfg(feature = "split")]
+    pub fn threshold(&self) -> u8 {
+        self.thresh
+    }
 }
@@ -0,0 +1,121 @@
+use crate::keyboard::ScanableMatrix;
+use bitvec::prelude::*;
+use gd32vf103xx_hal::gpio::{Input, Output, PullDown, PullUp, PushPull, Pxx};
+use gd32vf103xx_hal::pac::{gpioa::RegisterBlock, GPIOA, GPIOB, GPIOC, GPIOD, GPIOE};
+use riscv::asm::delay;
+
+//KeyMatrix working on the GPIO registers. All address pins sit on one port and all data pins
+//on one port, a single BOP write moves the strobe to the next address pin and a single ISTAT
+//read takes all data pins of it. The keys get updated after the strobe went through all
+//address pins, so the time spent with a strobed pin stays short.
+//~1µs for the diode capacitance to discharge and the data pins to follow the strobe, the same
+//as KeyMatrix. Shorter and a pressed key shows up on the next address pin as well.
+const SETTLE: u32 = 100;
+
+//The pulls of the data pins give the direction of the diodes. Against pull-downs the strobed
+//address pin is driven high, against pull-ups low, and the data pins of pressed keys read like
+//the strobe.
+pub trait Pull {
+    const STROBE_HIGH: bool;
+}
+impl Pull for PullDown {
+    const STROBE_HIGH: bool = true;
+}
+impl Pull for PullUp {
+    const STROBE_HIGH: bool = false;
+}
+
+pub struct PortMatrix<M, const AC: usize, const DC: usize> {
+    //the pins stay configured as long as the matrix owns them
+    _address: [Pxx<Output<PushPull>>; AC],
+    _data: [Pxx<Input<M>>; DC],
+    address_port: &'static RegisterBlock,
+    data_port: &'static RegisterBlock,
+    address_masks: [u32; AC],
+    data_pins: [u8; DC],
+    offset: u8,
+}
+
+fn port<MODE>(pin: &Pxx<MODE>) -> &'static RegisterBlock {
+    //NOTE(unsafe) ISTAT is only read and BOP/BC are stateless, like the HAL does it
+    unsafe {
+        match pin {
+            Pxx::PAx(_) => &*GPIOA::ptr(),
+            Pxx::PBx(_) => &*GPIOB::ptr(),
+            Pxx::PCx(_) => &*GPIOC::ptr(),
+            Pxx::PDx(_) => &*GPIOD::ptr(),
+            Pxx::PEx(_) => &*GPIOE::ptr(),
+        }
+    }
+}
+
+impl<M: Pull, const AC: usize, const DC: usize> PortMatrix<M, AC, DC> {
+    pub fn new(
+        address_pins: [Pxx<Output<PushPull>>; AC],
+        data_pins: [Pxx<Input<M>>; DC],
+        offset: u8,
+    ) -> Self {
+        let address_port = port(&address_pins[0]);
+        let data_port = port(&data_pins[0]);
+        assert!(address_pins
+            .iter()
+            .all(|pin| core::ptr::eq(port(pin), address_port)));
+        assert!(data_pins
+            .iter()
+            .all(|pin| core::ptr::eq(port(pin), data_port)));
+        let matrix = Self {
+            address_masks: core::array::from_fn(|i| 1u32 << address_pins[i].pin_number()),
+            data_pins: core::array::from_fn(|i| data_pins[i].pin_number()),
+            _address: address_pins,
+            _data: data_pins,
+            address_port,
+            data_port,
+            offset,
+        };
+        matrix.release(matrix.address_masks.iter().fold(0, |all, mask| all | mask));
+        matrix
+    }
+    //BOP sets the pins of the low half word and clears the ones of the high half word
+    fn strobe(&self, strobe: u32, release: u32) {
+        let bits = if M::STROBE_HIGH {
+            strobe | release << 16
+        } else {
+            strobe << 16 | release
+        };
+        self.address_port.bop.write(|w| unsafe { w.bits(bits) });
+    }
+    fn release(&self, release: u32) {
+        self.strobe(0, release);
+    }
+}
+
+impl<M: Pull, const AC: usize, const DC: usize> ScanableMatrix for PortMatrix<M, AC, DC> {
+    fn scan(&mut self, keybuffer: &mut BitArr!(for 256)) {
+        #[cfg(debug_assertions)]
+        {
+            assert!(keybuffer.len() >= (self.offset as usize + AC * DC) * 2);
+        }
+        let mut samples = [0u32; AC];
+        let mut strobed = 0;
+        for (sample, mask) in samples.iter_mut().zip(self.address_masks) {
+            self.strobe(mask, strobed);
+            strobed = mask;
+            unsafe {
+                delay(SETTLE);
+            }
+            *sample = self.data_port.istat.read().bits();
+        }
+        self.release(strobed);
+        for (i, sample) in samples.iter().enumerate() {
+            for (j, pin) in self.data_pins.iter().enumerate() {
+                let pressed = (sample >> pin & 1 != 0) == M::STROBE_HIGH;
+                let key = (self.offset as usize + i * DC + j) * 2;
+                let bits = keybuffer.get_mut(key..=(key + 1)).unwrap();
+                if pressed != *bits.get(0).unwrap() {
+                    bits.set(0, pressed); //key bit
+                    bits.set(1, true); //change bit
+                }
+            }
+        }
+    }
+}
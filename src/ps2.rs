@@ -175,3 +175,26 @@ where
         }
     }
 }
+
+//Repeat delay and rate of held keys, the host sets them with 0xF3
+#[derive(Clone, Copy)]
+pub struct Typematic {
+    //ms until the first repeat
+    pub delay: u16,
+    //ms between repeats
+    pub period: u16,
+}
+
+impl Typematic {
+    //500 ms and 10.9 repeats per second
+    pub const DEFAULT: Self = Self::from_byte(0x2B);
+    //bits 5-6 give the delay in steps of 250 ms, the rate is (8 + bits 0-2) * 2^bits 3-4 * 4.17 ms
+    pub const fn from_byte(byte: u8) -> Self {
+        let a = (byte & 0x07) as u32;
+        let b = ((byte >> 3) & 0x03) as u32;
+        Self {
+            delay: ((byte >> 5) & 0x03) as u16 * 250 + 250,
+            period: ((8 + a) * (1 << b) * 417 / 100) as u16,
+        }
+    }
+}
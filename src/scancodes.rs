@@ -0,0 +1,188 @@
+use crate::keycodes::{KeyCode, Modifiers};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScancodeSet {
+    One = 1,
+    Two = 2,
+    Three = 3,
+}
+
+impl ScancodeSet {
+    pub fn from_u8(set: u8) -> Option<Self> {
+        match set {
+            1 => Some(Self::One),
+            2 => Some(Self::Two),
+            3 => Some(Self::Three),
+            _ => None,
+        }
+    }
+}
+
+//Turns key presses into the bytes of the selected scancode set. Sets 1 and 2 come with the
+//quirks of the original keyboards: the navigation keys are wrapped in fake shifts that undo
+//the shift or NumLock state, PrintScreen and Pause change their codes with the modifiers held
+//and Pause has no break code at all. The modifiers passed are the ones the host sees.
+pub struct Scancodes {
+    pub set: ScancodeSet,
+    //set by the host with 0xED, changes the fake shifts
+    pub num_lock: bool,
+}
+
+impl Scancodes {
+    pub const fn new() -> Self {
+        Self {
+            set: ScancodeSet::Two,
+            num_lock: false,
+        }
+    }
+    pub fn make<E: Extend<u8>>(&self, buffer: &mut E, code: KeyCode, modifiers: Modifiers) {
+        use KeyCode::*;
+        if self.set == ScancodeSet::Three {
+            buffer.extend(code.set3().iter().copied());
+            return;
+        }
+        match code {
+            PrintScreen if modifiers.intersects(Modifiers::ALT) => buffer.extend([self.sysrq()]),
+            PrintScreen if modifiers.intersects(Modifiers::CTRL | Modifiers::SHIFT) => {
+                self.extend(buffer, code)
+            }
+            PrintScreen => {
+                self.fake_shift(buffer, LShift, true);
+                self.extend(buffer, code);
+            }
+            //Ctrl+Pause is Break, that one has a break code but sends it right away
+            Pause if modifiers.intersects(Modifiers::CTRL) => {
+                self.extend_prefixed(buffer, ScrollLock);
+                self.release_prefixed(buffer, ScrollLock);
+            }
+            Pause => self.extend(buffer, code),
+            _ => match self.fake_shifts(code, modifiers) {
+                FakeShifts::Shifts(shifts) => {
+                    for shift in shifts.keycodes() {
+                        self.fake_shift(buffer, shift, false);
+                    }
+                    self.extend(buffer, code);
+                }
+                FakeShifts::NumLock => {
+                    self.fake_shift(buffer, LShift, true);
+                    self.extend(buffer, code);
+                }
+                FakeShifts::None => self.extend(buffer, code),
+            },
+        }
+    }
+    pub fn release<E: Extend<u8>>(&self, buffer: &mut E, code: KeyCode, modifiers: Modifiers) {
+        use KeyCode::*;
+        if self.set == ScancodeSet::Three {
+            buffer.extend([0xF0]);
+            buffer.extend(code.set3().iter().copied());
+            return;
+        }
+        match code {
+            PrintScreen if modifiers.intersects(Modifiers::ALT) => {
+                self.release_codes(buffer, &[self.sysrq()])
+            }
+            PrintScreen if modifiers.intersects(Modifiers::CTRL | Modifiers::SHIFT) => {
+                self.release_plain(buffer, code)
+            }
+            PrintScreen => {
+                self.release_plain(buffer, code);
+                self.fake_shift(buffer, LShift, false);
+            }
+            Pause => {}
+            _ => match self.fake_shifts(code, modifiers) {
+                FakeShifts::Shifts(shifts) => {
+                    self.release_plain(buffer, code);
+                    for shift in shifts.keycodes() {
+                        self.fake_shift(buffer, shift, true);
+                    }
+                }
+                FakeShifts::NumLock => {
+                    self.release_plain(buffer, code);
+                    self.fake_shift(buffer, LShift, false);
+                }
+                FakeShifts::None => self.release_plain(buffer, code),
+            },
+        }
+    }
+    pub fn modifier_change<E: Extend<u8>>(&self, buffer: &mut E, from: Modifiers, to: Modifiers) {
+        for code in (from & !to).keycodes() {
+            self.release(buffer, code, to);
+        }
+        for code in (to & !from).keycodes() {
+            self.make(buffer, code, to);
+        }
+    }
+    fn fake_shifts(&self, code: KeyCode, modifiers: Modifiers) -> FakeShifts {
+        use KeyCode::*;
+        let shifts = modifiers & Modifiers::SHIFT;
+        match code {
+            Insert | Home | PageUp | Delete | End | PageDown | Right | Left | Down | Up => {
+                if !shifts.is_empty() {
+                    FakeShifts::Shifts(shifts)
+                } else if self.num_lock {
+                    FakeShifts::NumLock
+                } else {
+                    FakeShifts::None
+                }
+            }
+            KpSlash if !shifts.is_empty() => FakeShifts::Shifts(shifts),
+            _ => FakeShifts::None,
+        }
+    }
+    fn codes(&self, code: KeyCode) -> &'static [u8] {
+        match self.set {
+            ScancodeSet::One => code.set1(),
+            ScancodeSet::Two => code.set2(),
+            ScancodeSet::Three => code.set3(),
+        }
+    }
+    fn extend<E: Extend<u8>>(&self, buffer: &mut E, code: KeyCode) {
+        buffer.extend(self.codes(code).iter().copied());
+    }
+    fn extend_prefixed<E: Extend<u8>>(&self, buffer: &mut E, code: KeyCode) {
+        buffer.extend([0xE0]);
+        self.extend(buffer, code);
+    }
+    //break of a key without a fake shift
+    fn release_plain<E: Extend<u8>>(&self, buffer: &mut E, code: KeyCode) {
+        self.release_codes(buffer, self.codes(code));
+    }
+    //the E0 prefix stays in front
+    fn release_codes<E: Extend<u8>>(&self, buffer: &mut E, make: &[u8]) {
+        if let Some((last, prefix)) = make.split_last() {
+            buffer.extend(prefix.iter().copied());
+            match self.set {
+                ScancodeSet::One => buffer.extend([last | 0x80]),
+                _ => buffer.extend([0xF0, *last]),
+            }
+        }
+    }
+    fn release_prefixed<E: Extend<u8>>(&self, buffer: &mut E, code: KeyCode) {
+        buffer.extend([0xE0]);
+        self.release_plain(buffer, code);
+    }
+    //Alt+PrintScreen has a code of its own
+    fn sysrq(&self) -> u8 {
+        match self.set {
+            ScancodeSet::One => 0x54,
+            _ => 0x84,
+        }
+    }
+    //E0 followed by the make or break of the shift key
+    fn fake_shift<E: Extend<u8>>(&self, buffer: &mut E, shift: KeyCode, pressed: bool) {
+        if pressed {
+            self.extend_prefixed(buffer, shift);
+        } else {
+            self.release_prefixed(buffer, shift);
+        }
+    }
+}
+
+enum FakeShifts {
+    None,
+    //the shifts held get released around the key
+    Shifts(Modifiers),
+    //a shift gets pressed around the key
+    NumLock,
+}
@@ -0,0 +1,110 @@
+use crate::eeprom::{Eeprom, BLOB_HEADER_LEN, SETTINGS_ADDRESS, SETTINGS_SIZE};
+use crate::handedness::Hand;
+use crate::host_layouts::HostLayout;
+use crate::layout_emulation::EmulatedLayout;
+use crate::unicode::UnicodeMode;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use heapless::Vec;
+
+pub const PROFILE_COUNT: usize = 4;
+const MAGIC: [u8; 2] = *b"ST";
+
+//Settings that depend on the host the keyboard is plugged into
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    pub host_layout: HostLayout,
+    pub unicode_mode: UnicodeMode,
+}
+
+#[derive(Clone, Copy)]
+pub struct Settings {
+    pub profiles: [Profile; PROFILE_COUNT],
+    pub active_profile: u8,
+    pub emulated_layout: EmulatedLayout,
+    //keys pressed with Ctrl, Alt or Gui keep their QWERTY position, so Ctrl+C stays C
+    pub shortcut_passthrough: bool,
+    //hand of a split half without a strap pin
+    pub hand: Option<Hand>,
+    //letters and numbers held a little longer come out shifted, see AUTO_SHIFT
+    pub auto_shift: bool,
+}
+
+impl Profile {
+    pub const fn new() -> Self {
+        Self {
+            host_layout: HostLayout::Us,
+            unicode_mode: UnicodeMode::Disabled,
+        }
+    }
+}
+
+impl Settings {
+    pub const fn new() -> Self {
+        Self {
+            profiles: [Profile::new(); PROFILE_COUNT],
+            active_profile: 0,
+            emulated_layout: EmulatedLayout::Off,
+            shortcut_passthrough: true,
+            hand: None,
+            auto_shift: false,
+        }
+    }
+    pub fn profile(&self) -> &Profile {
+        &self.profiles[self.active_profile as usize % PROFILE_COUNT]
+    }
+    pub fn profile_mut(&mut self) -> &mut Profile {
+        &mut self.profiles[self.active_profile as usize % PROFILE_COUNT]
+    }
+    fn encoded(&self) -> Vec<u8, { 5 + PROFILE_COUNT * 2 }> {
+        let mut data = Vec::new();
+        let _ = data.extend_from_slice(&[
+            self.active_profile,
+            self.emulated_layout as u8,
+            self.shortcut_passthrough as u8,
+        ]);
+        for profile in &self.profiles {
+            let _ =
+                data.extend_from_slice(&[profile.host_layout as u8, profile.unicode_mode as u8]);
+        }
+        let _ = data.push(self.hand.map_or(0, |hand| hand as u8));
+        let _ = data.push(self.auto_shift as u8);
+        data
+    }
+    //keeps the defaults for anything it doesn't know
+    fn set_encoded(&mut self, data: &[u8]) {
+        if data.len() < 3 + PROFILE_COUNT * 2 {
+            return;
+        }
+        self.active_profile = data[0] % PROFILE_COUNT as u8;
+        self.emulated_layout = EmulatedLayout::from_u8(data[1]).unwrap_or(EmulatedLayout::Off);
+        self.shortcut_passthrough = data[2] != 0;
+        let (profiles, rest) = data[3..].split_at(PROFILE_COUNT * 2);
+        for (profile, stored) in self.profiles.iter_mut().zip(profiles.chunks(2)) {
+            if let Some(layout) = HostLayout::from_u8(stored[0]) {
+                profile.host_layout = layout;
+            }
+            if let Some(mode) = UnicodeMode::from_u8(stored[1]) {
+                profile.unicode_mode = mode;
+            }
+        }
+        //settings saved before the hand was added end here
+        self.hand = rest.first().and_then(|hand| Hand::from_u8(*hand));
+        //and the ones before auto-shift here
+        self.auto_shift = rest.get(1).is_some_and(|auto_shift| *auto_shift != 0);
+    }
+    pub fn load<I2C, E>(&mut self, eeprom: &mut Eeprom<I2C>) -> Result<(), E>
+    where
+        I2C: Write<Error = E> + WriteRead<Error = E>,
+    {
+        let mut data: Vec<u8, { SETTINGS_SIZE - BLOB_HEADER_LEN }> = Vec::new();
+        eeprom.read_blob(SETTINGS_ADDRESS, MAGIC, &mut data)?;
+        self.set_encoded(&data);
+        Ok(())
+    }
+    pub fn save<I2C, E>(&self, eeprom: &mut Eeprom<I2C>) -> Result<(), E>
+    where
+        I2C: Write<Error = E> + WriteRead<Error = E>,
+    {
+        eeprom.write_blob(SETTINGS_ADDRESS, MAGIC, &self.encoded())
+    }
+}
@@ -0,0 +1,238 @@
+use crate::keyboard::ScanableMatrix;
+use bitvec::prelude::*;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+//Matrix behind shift registers on SPI (mode 0, MSB first). A chain of 74HC595 on MOSI drives
+//the columns high one after the other, a chain of 74HC165 on MISO reads the rows with their
+//pull-downs. Both chains share SCK and the latch pin: low loads the rows into the 74HC165,
+//the rising edge puts the column shifted in before on the 74HC595 outputs. So every transfer
+//shifts out the next column and shifts in the rows loaded while the column before it was
+//driven, a scan takes COLS + 2 transfers. Up to MAX_CHAIN registers per chain.
+pub const MAX_CHAIN: usize = 8;
+
+pub struct ShiftRegisterMatrix<SPI, L, const COLS: usize, const ROWS: usize> {
+    spi: SPI,
+    latch: L,
+    offset: u8,
+}
+
+impl<SPI, L, E, const COLS: usize, const ROWS: usize> ShiftRegisterMatrix<SPI, L, COLS, ROWS>
+where
+    SPI: Transfer<u8, Error = E>,
+    L: OutputPin,
+{
+    pub fn new(spi: SPI, mut latch: L, offset: u8) -> Self {
+        assert!(COLS <= MAX_CHAIN * 8 && ROWS <= MAX_CHAIN * 8);
+        let _ = latch.set_high();
+        Self { spi, latch, offset }
+    }
+    pub fn free(self) -> (SPI, L) {
+        (self.spi, self.latch)
+    }
+    //registers the transfers need to reach the end of the longer chain
+    fn chain_len() -> usize {
+        COLS.div_ceil(8).max(ROWS.div_ceil(8))
+    }
+    //Shifts out the given column (none for all low) and returns the rows. The first byte out
+    //ends up in the last 74HC595, the first byte in comes from the first 74HC165.
+    fn transfer(&mut self, column: Option<usize>) -> Result<[u8; MAX_CHAIN], E> {
+        let len = Self::chain_len();
+        let mut buffer = [0u8; MAX_CHAIN];
+        if let Some(column) = column {
+            buffer[len - 1 - column / 8] = 1 << (column % 8);
+        }
+        self.spi.transfer(&mut buffer[..len])?;
+        let _ = self.latch.set_low();
+        let _ = self.latch.set_high();
+        Ok(buffer)
+    }
+}
+
+impl<SPI, L, E, const COLS: usize, const ROWS: usize> ScanableMatrix
+    for ShiftRegisterMatrix<SPI, L, COLS, ROWS>
+where
+    SPI: Transfer<u8, Error = E>,
+    L: OutputPin,
+{
+    fn scan(&mut self, keybuffer: &mut BitArr!(for 256)) {
+        #[cfg(debug_assertions)]
+        {
+            assert!(keybuffer.len() >= (self.offset as usize + COLS * ROWS) * 2);
+        }
+        //the rows of a column come in two transfers after it got shifted out
+        for step in 0..COLS + 2 {
+            let rows = match self.transfer(if step < COLS { Some(step) } else { None }) {
+                Ok(rows) => rows,
+                //the rest of the scan waits for the next one, a ms away
+                Err(_) => {
+                    let _ = self.transfer(None);
+                    return;
+                }
+            };
+            if step < 2 {
+                continue;
+            }
+            let column = step - 2;
+            for row in 0..ROWS {
+                let pressed = rows[row / 8] & (1 << (row % 8)) != 0;
+                let key = (self.offset as usize + column * ROWS + row) * 2;
+                let bits = keybuffer.get_mut(key..=(key + 1)).unwrap();
+                if pressed != *bits.get(0).unwrap() {
+                    bits.set(0, pressed); //key bit
+                    bits.set(1, true); //change bit
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use heapless::Vec;
+
+    const OFFSET: usize = 3;
+
+    //Both chains bit by bit, register 0 is the one next to the MCU. pressed holds
+    //(column, row) pairs, a row reads high while the column of a pressed key on it is driven.
+    struct Chains {
+        shift_595: [u8; MAX_CHAIN],
+        outputs_595: [u8; MAX_CHAIN],
+        len_595: usize,
+        shift_165: [u8; MAX_CHAIN],
+        len_165: usize,
+        pressed: Vec<(usize, usize), 8>,
+        transfers: usize,
+    }
+
+    impl Chains {
+        fn new(len_595: usize, len_165: usize, pressed: &[(usize, usize)]) -> Self {
+            Self {
+                //whatever the 74HC595 held at power on
+                shift_595: [0xFF; MAX_CHAIN],
+                outputs_595: [0; MAX_CHAIN],
+                len_595,
+                shift_165: [0; MAX_CHAIN],
+                len_165,
+                pressed: Vec::from_slice(pressed).unwrap(),
+                transfers: 0,
+            }
+        }
+        fn clock(&mut self, mosi: u8) -> u8 {
+            let miso = self.shift_165[0] >> 7;
+            for i in 0..self.len_165 {
+                let next = if i + 1 < self.len_165 {
+                    self.shift_165[i + 1] >> 7
+                } else {
+                    0
+                };
+                self.shift_165[i] = (self.shift_165[i] << 1) | next;
+            }
+            for i in (0..self.len_595).rev() {
+                let previous = if i > 0 {
+                    self.shift_595[i - 1] >> 7
+                } else {
+                    mosi
+                };
+                self.shift_595[i] = (self.shift_595[i] << 1) | previous;
+            }
+            miso
+        }
+        fn load_rows(&mut self) {
+            self.shift_165 = [0; MAX_CHAIN];
+            for &(column, row) in &self.pressed {
+                if self.outputs_595[column / 8] & (1 << (column % 8)) != 0 {
+                    self.shift_165[row / 8] |= 1 << (row % 8);
+                }
+            }
+        }
+    }
+
+    struct Spi<'a>(&'a RefCell<Chains>);
+
+    impl Transfer<u8> for Spi<'_> {
+        type Error = ();
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], ()> {
+            let mut chains = self.0.borrow_mut();
+            chains.transfers += 1;
+            for word in words.iter_mut() {
+                let mut read = 0;
+                for bit in (0..8).rev() {
+                    read |= chains.clock((*word >> bit) & 1) << bit;
+                }
+                *word = read;
+            }
+            Ok(words)
+        }
+    }
+
+    struct Latch<'a>(&'a RefCell<Chains>);
+
+    impl OutputPin for Latch<'_> {
+        type Error = ();
+        fn set_low(&mut self) -> Result<(), ()> {
+            self.0.borrow_mut().load_rows();
+            Ok(())
+        }
+        fn set_high(&mut self) -> Result<(), ()> {
+            let mut chains = self.0.borrow_mut();
+            chains.outputs_595 = chains.shift_595;
+            Ok(())
+        }
+    }
+
+    fn scan_chains<const COLS: usize, const ROWS: usize>(
+        len_595: usize,
+        len_165: usize,
+        pressed: &[(usize, usize)],
+    ) {
+        let chains = RefCell::new(Chains::new(len_595, len_165, pressed));
+        let mut matrix: ShiftRegisterMatrix<_, _, COLS, ROWS> =
+            ShiftRegisterMatrix::new(Spi(&chains), Latch(&chains), OFFSET as u8);
+        let mut keybuffer: BitArr!(for 256) = BitArray::ZERO;
+        let mut keys: Vec<usize, 8> = pressed
+            .iter()
+            .map(|(column, row)| OFFSET + column * ROWS + row)
+            .collect();
+        keys.sort_unstable();
+
+        matrix.scan(&mut keybuffer);
+        let found: Vec<usize, 8> = (0..128).filter(|key| keybuffer[key * 2]).collect();
+        assert_eq!(found, keys);
+        let changed: Vec<usize, 8> = (0..128).filter(|key| keybuffer[key * 2 + 1]).collect();
+        assert_eq!(changed, keys);
+        assert_eq!(chains.borrow().transfers, COLS + 2);
+        //no column left driven between scans
+        assert!(chains.borrow().outputs_595[..len_595]
+            .iter()
+            .all(|outputs| *outputs == 0));
+
+        keybuffer.fill(false);
+        for key in &keys {
+            keybuffer.set(key * 2, true);
+        }
+        chains.borrow_mut().pressed.clear();
+        matrix.scan(&mut keybuffer);
+        assert!((0..128).all(|key| !keybuffer[key * 2]));
+        let changed: Vec<usize, 8> = (0..128).filter(|key| keybuffer[key * 2 + 1]).collect();
+        assert_eq!(changed, keys);
+    }
+
+    #[test]
+    fn single_registers() {
+        scan_chains::<8, 8>(1, 1, &[(0, 0), (7, 7), (3, 5)]);
+    }
+
+    #[test]
+    fn longer_column_chain() {
+        scan_chains::<12, 5>(2, 1, &[(11, 4), (8, 0), (0, 2)]);
+        scan_chains::<16, 7>(2, 1, &[(15, 6), (9, 1)]);
+    }
+
+    #[test]
+    fn longer_row_chain() {
+        scan_chains::<6, 14>(1, 2, &[(5, 13), (2, 9), (0, 0)]);
+    }
+}
@@ -0,0 +1,825 @@
+use crate::crc::crc8;
+use crate::keyboard::ScanableMatrix;
+use bitvec::prelude::*;
+use embedded_hal::serial::{Read, Write};
+use heapless::{String, Vec};
+use ringbuffer::{
+    ConstGenericRingBuffer, RingBuffer, RingBufferExt, RingBufferRead, RingBufferWrite,
+};
+
+//Link between the halves of a split board over a USART. The secondary half sends the changes
+//of its keys, the primary half acks every frame and the secondary resends frames that didn't
+//get acked. Frames are [SYNC][kind][seq][payload len][payload][crc8 over kind..payload].
+//The primary sends the state the secondary shows on its LED and display down the same link,
+//unacked and repeated every HEARTBEAT. Both sides ignore the frame kinds they send themselves,
+//so a single-wire half-duplex USART that hears its own bytes works as well.
+pub const SYNC: u8 = 0xA5;
+pub const MAX_PAYLOAD: usize = 16;
+//the key index of a change takes 7 bits and the whole state fits into one frame
+pub const MAX_KEYS: usize = MAX_PAYLOAD * 8;
+//the link gets polled once per scan, every ms
+pub const RETRY_TIMEOUT: u32 = 20;
+//after this many resends the secondary sends all of its keys instead
+pub const MAX_RETRIES: u8 = 5;
+//the secondary sends all of its keys when it had nothing to send for a while
+pub const HEARTBEAT: u32 = 100;
+//the primary releases the keys of the secondary when it goes quiet
+pub const LINK_TIMEOUT: u32 = 500;
+const PRESSED: u8 = 0x80;
+pub const DISPLAY_LINES: usize = 3;
+//the line number takes the first byte of the payload
+pub type DisplayLine = String<{ MAX_PAYLOAD - 1 }>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum FrameKind {
+    //a byte per key change, the key index with the top bit set for presses
+    Changes = 0x01,
+    //a bit per key of the secondary half
+    State = 0x02,
+    //the seq of the frame the primary got
+    Ack = 0x03,
+    //[layers, brightness, host LEDs] from the primary
+    Status = 0x04,
+    //[line, text] for the display of the secondary
+    Display = 0x05,
+}
+
+impl FrameKind {
+    pub fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            0x01 => Some(Self::Changes),
+            0x02 => Some(Self::State),
+            0x03 => Some(Self::Ack),
+            0x04 => Some(Self::Status),
+            0x05 => Some(Self::Display),
+            _ => None,
+        }
+    }
+}
+
+//What the secondary half shows of the keyboard state
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct HalfStatus {
+    //bit per active layer
+    pub layers: u8,
+    //threshold of the LedPwm
+    pub brightness: u8,
+    //lock LEDs as the host set them with 0xED
+    pub host_leds: u8,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Frame {
+    pub kind: FrameKind,
+    pub seq: u8,
+    pub payload: Vec<u8, MAX_PAYLOAD>,
+}
+
+enum Decoder {
+    Sync,
+    Header(Vec<u8, 3>),
+    Payload(FrameKind, u8, Vec<u8, MAX_PAYLOAD>, usize),
+    Crc(Frame),
+}
+
+//Frame reassembly and a send queue on top of a serial port. Bytes with a framing error or a
+//bad crc drop the frame and the decoder waits for the next SYNC.
+pub struct Link<R, T> {
+    rx: R,
+    tx: T,
+    decoder: Decoder,
+    send_queue: ConstGenericRingBuffer<u8, 64>,
+}
+
+impl<R, T> Link<R, T>
+where
+    R: Read<u8>,
+    T: Write<u8>,
+{
+    pub fn new(rx: R, tx: T) -> Self {
+        Self {
+            rx,
+            tx,
+            decoder: Decoder::Sync,
+            send_queue: ConstGenericRingBuffer::new(),
+        }
+    }
+    pub fn send(&mut self, kind: FrameKind, seq: u8, payload: &[u8]) {
+        let header = [kind as u8, seq, payload.len() as u8];
+        let mut crc_data: Vec<u8, { MAX_PAYLOAD + 3 }> = Vec::new();
+        let _ = crc_data.extend_from_slice(&header);
+        let _ = crc_data.extend_from_slice(payload);
+        self.send_queue.push(SYNC);
+        self.send_queue.extend(crc_data.iter().copied());
+        self.send_queue.push(crc8(&crc_data));
+    }
+    pub fn is_sending(&self) -> bool {
+        !self.send_queue.is_empty()
+    }
+    //hands the queued bytes to the port until it is busy
+    pub fn flush(&mut self) {
+        while let Some(byte) = self.send_queue.peek().copied() {
+            if self.tx.write(byte).is_err() {
+                return;
+            }
+            self.send_queue.skip();
+        }
+    }
+    pub fn receive(&mut self) -> Option<Frame> {
+        loop {
+            let byte = match self.rx.read() {
+                Ok(byte) => byte,
+                Err(nb::Error::WouldBlock) => return None,
+                Err(nb::Error::Other(_)) => {
+                    self.decoder = Decoder::Sync;
+                    continue;
+                }
+            };
+            if let Some(frame) = self.decode(byte) {
+                return Some(frame);
+            }
+        }
+    }
+    fn decode(&mut self, byte: u8) -> Option<Frame> {
+        let decoder = core::mem::replace(&mut self.decoder, Decoder::Sync);
+        self.decoder = match decoder {
+            Decoder::Sync if byte == SYNC => Decoder::Header(Vec::new()),
+            Decoder::Sync => Decoder::Sync,
+            Decoder::Header(mut header) => {
+                let _ = header.push(byte);
+                match header[..] {
+                    [kind, seq, len] => match FrameKind::from_u8(kind) {
+                        Some(kind) if len as usize <= MAX_PAYLOAD => {
+                            Decoder::Payload(kind, seq, Vec::new(), len as usize).complete()
+                        }
+                        _ => Decoder::Sync,
+                    },
+                    _ => Decoder::Header(header),
+                }
+            }
+            Decoder::Payload(kind, seq, mut payload, len) => {
+                let _ = payload.push(byte);
+                Decoder::Payload(kind, seq, payload, len).complete()
+            }
+            Decoder::Crc(frame) => {
+                let mut crc_data: Vec<u8, { MAX_PAYLOAD + 3 }> = Vec::new();
+                let _ = crc_data.extend_from_slice(&[
+                    frame.kind as u8,
+                    frame.seq,
+                    frame.payload.len() as u8,
+                ]);
+                let _ = crc_data.extend_from_slice(&frame.payload);
+                if crc8(&crc_data) == byte {
+                    return Some(frame);
+                }
+                Decoder::Sync
+            }
+        };
+        None
+    }
+}
+
+impl Decoder {
+    fn complete(self) -> Self {
+        match self {
+            Decoder::Payload(kind, seq, payload, len) if payload.len() == len => {
+                Decoder::Crc(Frame { kind, seq, payload })
+            }
+            decoder => decoder,
+        }
+    }
+}
+
+//Secondary half, sends the keys of its own matrix. The key buffer is the one its matrices
+//scan into, with 2 bits per key starting at key 0.
+pub struct SplitSender<R, T, const KEYS: usize> {
+    link: Link<R, T>,
+    //keys as the primary knows them once the frame in flight is acked
+    sent: BitArr!(for MAX_KEYS),
+    seq: u8,
+    //frame waiting for its ack and the time it was sent
+    in_flight: Option<(Frame, u32)>,
+    retries: u8,
+    //the primary may not know the keys, like after a reset of either half
+    resync: bool,
+    last_sent: u32,
+    ticks: u32,
+    status: HalfStatus,
+    lines: [DisplayLine; DISPLAY_LINES],
+    //status or lines changed since the last take_changed
+    changed: bool,
+}
+
+impl<R, T, const KEYS: usize> SplitSender<R, T, KEYS>
+where
+    R: Read<u8>,
+    T: Write<u8>,
+{
+    pub fn new(rx: R, tx: T) -> Self {
+        assert!(KEYS <= MAX_KEYS);
+        Self {
+            link: Link::new(rx, tx),
+            sent: BitArray::ZERO,
+            seq: 0,
+            in_flight: None,
+            retries: 0,
+            resync: true,
+            last_sent: 0,
+            ticks: 0,
+            status: HalfStatus::default(),
+            lines: Default::default(),
+            changed: true,
+        }
+    }
+    pub fn status(&self) -> HalfStatus {
+        self.status
+    }
+    pub fn lines(&self) -> &[DisplayLine; DISPLAY_LINES] {
+        &self.lines
+    }
+    pub fn take_changed(&mut self) -> bool {
+        core::mem::replace(&mut self.changed, false)
+    }
+    //called once per scan
+    pub fn poll(&mut self, keybuffer: &BitArr!(for 256)) {
+        self.ticks = self.ticks.wrapping_add(1);
+        while let Some(frame) = self.link.receive() {
+            self.receive(frame);
+        }
+        if let Some((frame, sent_at)) = &self.in_flight {
+            if self.ticks.wrapping_sub(*sent_at) >= RETRY_TIMEOUT {
+                if self.retries < MAX_RETRIES {
+                    self.retries += 1;
+                    let frame = frame.clone();
+                    self.transmit(frame);
+                } else {
+                    self.in_flight = None;
+                    self.retries = 0;
+                    self.resync = true;
+                }
+            }
+        }
+        if self.in_flight.is_none() {
+            if self.resync || self.ticks.wrapping_sub(self.last_sent) >= HEARTBEAT {
+                self.send_state(keybuffer);
+            } else {
+                self.send_changes(keybuffer);
+            }
+        }
+        self.link.flush();
+    }
+    fn receive(&mut self, frame: Frame) {
+        match (frame.kind, &frame.payload[..]) {
+            (FrameKind::Ack, _) => {
+                if matches!(&self.in_flight, Some((sent, _)) if sent.seq == frame.seq) {
+                    self.in_flight = None;
+                    self.retries = 0;
+                }
+            }
+            (FrameKind::Status, &[layers, brightness, host_leds]) => {
+                let status = HalfStatus {
+                    layers,
+                    brightness,
+                    host_leds,
+                };
+                self.changed |= status != self.status;
+                self.status = status;
+            }
+            (FrameKind::Display, [line, text @ ..]) => {
+                if let (Some(shown), Ok(text)) = (
+                    self.lines.get_mut(*line as usize),
+                    core::str::from_utf8(text),
+                ) {
+                    if shown.as_str() != text {
+                        shown.clear();
+                        let _ = shown.push_str(text);
+                        self.changed = true;
+                    }
+                }
+            }
+            //our own frames on a single wire
+            _ => {}
+        }
+    }
+    fn send_state(&mut self, keybuffer: &BitArr!(for 256)) {
+        let mut payload = Vec::new();
+        let _ = payload.resize(KEYS.div_ceil(8), 0);
+        for key in 0..KEYS {
+            let pressed = keybuffer[key * 2];
+            if pressed {
+                payload[key / 8] |= 1 << (key % 8);
+            }
+            self.sent.set(key, pressed);
+        }
+        self.resync = false;
+        self.start(FrameKind::State, payload);
+    }
+    fn send_changes(&mut self, keybuffer: &BitArr!(for 256)) {
+        let mut payload: Vec<u8, MAX_PAYLOAD> = Vec::new();
+        for key in 0..KEYS {
+            let pressed = keybuffer[key * 2];
+            if pressed != self.sent[key] {
+                if payload
+                    .push(key as u8 | if pressed { PRESSED } else { 0 })
+                    .is_err()
+                {
+                    break;
+                }
+                self.sent.set(key, pressed);
+            }
+        }
+        if !payload.is_empty() {
+            self.start(FrameKind::Changes, payload);
+        }
+    }
+    fn start(&mut self, kind: FrameKind, payload: Vec<u8, MAX_PAYLOAD>) {
+        self.seq = self.seq.wrapping_add(1);
+        self.transmit(Frame {
+            kind,
+            seq: self.seq,
+            payload,
+        });
+    }
+    fn transmit(&mut self, frame: Frame) {
+        self.link.send(frame.kind, frame.seq, &frame.payload);
+        self.last_sent = self.ticks;
+        self.in_flight = Some((frame, self.ticks));
+    }
+}
+
+//Primary half, the keys of the secondary show up at offset in the key buffer like the ones
+//of a matrix wired to the MCU.
+pub struct SplitMatrix<R, T, const KEYS: usize> {
+    link: Link<R, T>,
+    offset: u8,
+    //seq of the next changes, unknown until the secondary sent its state
+    expected: Option<u8>,
+    last_heard: u32,
+    ticks: u32,
+    status: HalfStatus,
+    lines: [DisplayLine; DISPLAY_LINES],
+    //bit per line and STATUS_DIRTY, waiting to be sent down
+    dirty: u8,
+    last_refresh: u32,
+}
+
+const STATUS_DIRTY: u8 = 0x80;
+
+impl<R, T, const KEYS: usize> SplitMatrix<R, T, KEYS>
+where
+    R: Read<u8>,
+    T: Write<u8>,
+{
+    pub fn new(rx: R, tx: T, offset: u8) -> Self {
+        assert!(KEYS <= MAX_KEYS);
+        Self {
+            link: Link::new(rx, tx),
+            offset,
+            expected: None,
+            last_heard: 0,
+            ticks: 0,
+            status: HalfStatus::default(),
+            lines: Default::default(),
+            dirty: 0,
+            last_refresh: 0,
+        }
+    }
+    pub fn is_connected(&self) -> bool {
+        self.expected.is_some()
+    }
+    pub fn set_status(&mut self, status: HalfStatus) {
+        if status != self.status {
+            self.status = status;
+            self.dirty |= STATUS_DIRTY;
+        }
+    }
+    //text that doesn't fit gets cut off
+    pub fn set_line(&mut self, line: usize, text: &str) {
+        if let Some(shown) = self.lines.get_mut(line) {
+            let mut new = DisplayLine::new();
+            for c in text.chars() {
+                if new.push(c).is_err() {
+                    break;
+                }
+            }
+            if *shown != new {
+                *shown = new;
+                self.dirty |= 1 << line;
+            }
+        }
+    }
+    //one frame per scan and only after the acks, so the keys don't wait for the display
+    fn send_down(&mut self) {
+        if self.ticks.wrapping_sub(self.last_refresh) >= HEARTBEAT {
+            self.last_refresh = self.ticks;
+            self.dirty = STATUS_DIRTY | ((1 << DISPLAY_LINES) - 1);
+        }
+        if self.link.is_sending() || self.dirty == 0 {
+            return;
+        }
+        if self.dirty & STATUS_DIRTY != 0 {
+            self.dirty &= !STATUS_DIRTY;
+            let status = self.status;
+            self.link.send(
+                FrameKind::Status,
+                0,
+                &[status.layers, status.brightness, status.host_leds],
+            );
+            return;
+        }
+        let line = self.dirty.trailing_zeros() as usize;
+        self.dirty &= !(1 << line);
+        let mut payload: Vec<u8, MAX_PAYLOAD> = Vec::new();
+        let _ = payload.push(line as u8);
+        let _ = payload.extend_from_slice(self.lines[line].as_bytes());
+        self.link.send(FrameKind::Display, 0, &payload);
+    }
+    fn set_key(&self, keybuffer: &mut BitArr!(for 256), key: usize, pressed: bool) {
+        if key >= KEYS {
+            return;
+        }
+        let index = (self.offset as usize + key) * 2;
+        if keybuffer[index] != pressed {
+            keybuffer.set(index, pressed); //key bit
+            keybuffer.set(index + 1, true); //change bit
+        }
+    }
+    fn receive(&mut self, keybuffer: &mut BitArr!(for 256), frame: Frame) {
+        match frame.kind {
+            //our own frames on a single wire
+            FrameKind::Ack | FrameKind::Status | FrameKind::Display => return,
+            FrameKind::State => {
+                for key in 0..KEYS {
+                    let pressed = frame
+                        .payload
+                        .get(key / 8)
+                        .is_some_and(|bits| bits & (1 << (key % 8)) != 0);
+                    self.set_key(keybuffer, key, pressed);
+                }
+            }
+            FrameKind::Changes if self.expected == Some(frame.seq) => {
+                for change in &frame.payload {
+                    self.set_key(
+                        keybuffer,
+                        (change & !PRESSED) as usize,
+                        change & PRESSED != 0,
+                    );
+                }
+            }
+            //the ack got lost and the secondary sent it again
+            FrameKind::Changes if self.expected == Some(frame.seq.wrapping_add(1)) => {}
+            //changes on top of keys we don't know, the secondary sends its state after a
+            //few unacked resends
+            FrameKind::Changes => return,
+        }
+        self.expected = Some(frame.seq.wrapping_add(1));
+        self.last_heard = self.ticks;
+        self.link.send(FrameKind::Ack, frame.seq, &[]);
+    }
+}
+
+impl<R, T, const KEYS: usize> ScanableMatrix for SplitMatrix<R, T, KEYS>
+where
+    R: Read<u8>,
+    T: Write<u8>,
+{
+    fn scan(&mut self, keybuffer: &mut BitArr!(for 256)) {
+        #[cfg(debug_assertions)]
+        {
+            assert!(keybuffer.len() >= (self.offset as usize + KEYS) * 2);
+        }
+        self.ticks = self.ticks.wrapping_add(1);
+        while let Some(frame) = self.link.receive() {
+            self.receive(keybuffer, frame);
+        }
+        if self.expected.is_some() && self.ticks.wrapping_sub(self.last_heard) >= LINK_TIMEOUT {
+            self.expected = None;
+            for key in 0..KEYS {
+                self.set_key(keybuffer, key, false);
+            }
+        }
+        self.send_down();
+        self.link.flush();
+    }
+}
+
+//Firmware of the secondary half, its matrix scans into a key buffer of its own and the keys
+//go to the primary
+pub struct SecondaryHalf<M, R, T, const KEYS: usize> {
+    matrix: M,
+    key_buffer: BitArr!(for 256),
+    sender: SplitSender<R, T, KEYS>,
+}
+
+impl<M, R, T, const KEYS: usize> SecondaryHalf<M, R, T, KEYS>
+where
+    M: ScanableMatrix,
+    R: Read<u8>,
+    T: Write<u8>,
+{
+    pub fn new(matrix: M, sender: SplitSender<R, T, KEYS>) -> Self {
+        Self {
+            matrix,
+            key_buffer: BitArray::ZERO,
+            sender,
+        }
+    }
+    pub fn scan(&mut self) {
+        self.matrix.scan(&mut self.key_buffer);
+        self.sender.poll(&self.key_buffer);
+    }
+    pub fn sender_mut(&mut self) -> &mut SplitSender<R, T, KEYS> {
+        &mut self.sender
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    use heapless::Deque;
+
+    //One direction of the cable. Damages one of every flip_every bytes and loses one of every
+    //drop_every bytes on average, 0 for never, with a fixed seed so a failure repeats.
+    struct Wire {
+        bytes: Deque<u8, 512>,
+        seed: u32,
+        flip_every: u32,
+        drop_every: u32,
+    }
+
+    impl Wire {
+        fn new(flip_every: u32, drop_every: u32) -> RefCell<Self> {
+            RefCell::new(Self {
+                bytes: Deque::new(),
+                seed: 12345,
+                flip_every,
+                drop_every,
+            })
+        }
+        fn random(&mut self) -> u32 {
+            self.seed = self.seed.wrapping_mul(1103515245).wrapping_add(12345);
+            self.seed >> 16
+        }
+        fn hits(&mut self, every: u32) -> bool {
+            every != 0 && self.random().is_multiple_of(every)
+        }
+    }
+
+    struct End<'a>(&'a RefCell<Wire>);
+
+    impl Read<u8> for End<'_> {
+        type Error = ();
+        fn read(&mut self) -> nb::Result<u8, ()> {
+            self.0
+                .borrow_mut()
+                .bytes
+                .pop_front()
+                .ok_or(nb::Error::WouldBlock)
+        }
+    }
+
+    impl Write<u8> for End<'_> {
+        type Error = ();
+        fn write(&mut self, mut byte: u8) -> nb::Result<(), ()> {
+            let mut wire = self.0.borrow_mut();
+            let (flip_every, drop_every) = (wire.flip_every, wire.drop_every);
+            if wire.hits(drop_every) {
+                return Ok(());
+            }
+            if wire.hits(flip_every) {
+                byte ^= 1 << (wire.random() % 8);
+            }
+            let _ = wire.bytes.push_back(byte);
+            Ok(())
+        }
+        fn flush(&mut self) -> nb::Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    fn frame(kind: FrameKind, seq: u8, payload: &[u8]) -> Frame {
+        Frame {
+            kind,
+            seq,
+            payload: Vec::from_slice(payload).unwrap(),
+        }
+    }
+
+    //bytes of a frame as the link sends them
+    fn encoded(kind: FrameKind, seq: u8, payload: &[u8]) -> Vec<u8, { MAX_PAYLOAD + 5 }> {
+        let wire = Wire::new(0, 0);
+        let mut link = Link::new(End(&wire), End(&wire));
+        link.send(kind, seq, payload);
+        link.flush();
+        let bytes = wire.borrow().bytes.iter().copied().collect();
+        bytes
+    }
+
+    #[test]
+    fn crc_rejects_damaged_frames() {
+        let wire = Wire::new(0, 0);
+        let mut link = Link::new(End(&wire), End(&wire));
+        let bytes = encoded(FrameKind::Changes, 7, &[0x83, 0x05]);
+        //every single bit error after the sync byte gets caught
+        for bit in 8..bytes.len() * 8 {
+            let mut damaged = bytes.clone();
+            damaged[bit / 8] ^= 1 << (bit % 8);
+            for byte in damaged {
+                wire.borrow_mut().bytes.push_back(byte).unwrap();
+            }
+            assert_eq!(link.receive(), None, "bit {}", bit);
+            wire.borrow_mut().bytes.clear();
+            link.decoder = Decoder::Sync;
+        }
+        //a frame with a lost byte gets dropped, the decoder picks up the next ones
+        for (i, byte) in bytes.iter().enumerate() {
+            if i != 3 {
+                wire.borrow_mut().bytes.push_back(*byte).unwrap();
+            }
+        }
+        for _ in 0..2 {
+            for byte in &bytes {
+                wire.borrow_mut().bytes.push_back(*byte).unwrap();
+            }
+        }
+        for _ in 0..2 {
+            assert_eq!(
+                link.receive(),
+                Some(frame(FrameKind::Changes, 7, &[0x83, 0x05]))
+            );
+        }
+        assert_eq!(link.receive(), None);
+    }
+
+    #[test]
+    fn resends_until_acked() {
+        let (up, down) = (Wire::new(0, 0), Wire::new(0, 0));
+        let mut sender: SplitSender<_, _, 8> = SplitSender::new(End(&down), End(&up));
+        //stands in for the primary
+        let mut primary = Link::new(End(&up), End(&down));
+        let mut keys = BitArray::ZERO;
+        sender.poll(&keys);
+        let state = primary.receive().unwrap();
+        assert_eq!(
+            (state.kind, &state.payload[..]),
+            (FrameKind::State, &[0][..])
+        );
+        primary.send(FrameKind::Ack, state.seq, &[]);
+        primary.flush();
+        keys.set(3 * 2, true);
+        sender.poll(&keys);
+        let changes = frame(FrameKind::Changes, state.seq.wrapping_add(1), &[0x83]);
+        assert_eq!(primary.receive(), Some(changes.clone()));
+        //no ack, the same frame comes again after the timeout
+        for _ in 1..RETRY_TIMEOUT {
+            sender.poll(&keys);
+            assert_eq!(primary.receive(), None);
+        }
+        sender.poll(&keys);
+        assert_eq!(primary.receive(), Some(changes.clone()));
+        //an ack for another frame doesn't count
+        primary.send(FrameKind::Ack, changes.seq.wrapping_add(1), &[]);
+        primary.flush();
+        for _ in 0..RETRY_TIMEOUT {
+            sender.poll(&keys);
+        }
+        assert_eq!(primary.receive(), Some(changes.clone()));
+        primary.send(FrameKind::Ack, changes.seq, &[]);
+        primary.flush();
+        keys.set(3 * 2, false);
+        sender.poll(&keys);
+        let next = frame(FrameKind::Changes, changes.seq.wrapping_add(1), &[0x03]);
+        assert_eq!(primary.receive(), Some(next.clone()));
+        //without acks the secondary gives up after MAX_RETRIES and sends its whole state
+        for _ in 0..MAX_RETRIES {
+            for _ in 0..RETRY_TIMEOUT {
+                sender.poll(&keys);
+            }
+            assert_eq!(primary.receive(), Some(next.clone()));
+        }
+        for _ in 0..RETRY_TIMEOUT {
+            sender.poll(&keys);
+        }
+        assert_eq!(
+            primary.receive(),
+            Some(frame(FrameKind::State, next.seq.wrapping_add(1), &[0]))
+        );
+    }
+
+    #[test]
+    fn duplicates_and_gaps() {
+        let (up, down) = (Wire::new(0, 0), Wire::new(0, 0));
+        //stands in for the secondary
+        let mut secondary = Link::new(End(&down), End(&up));
+        let mut matrix: SplitMatrix<_, _, 8> = SplitMatrix::new(End(&up), End(&down), 10);
+        let mut keys = BitArray::ZERO;
+        let mut acks = || {
+            let mut acked = None;
+            while let Some(frame) = secondary.receive() {
+                if frame.kind == FrameKind::Ack {
+                    acked = Some(frame.seq);
+                }
+            }
+            acked
+        };
+        let mut send = |kind, seq, payload: &[u8], keys: &mut BitArr!(for 256)| {
+            let mut link = Link::new(End(&down), End(&up));
+            link.send(kind, seq, payload);
+            link.flush();
+            matrix.scan(keys);
+            keys.set((10 + 2) * 2 + 1, false);
+        };
+        //changes before the state are ignored and not acked
+        send(FrameKind::Changes, 4, &[0x82], &mut keys);
+        assert!(!keys[(10 + 2) * 2]);
+        assert_eq!(acks(), None);
+        send(FrameKind::State, 4, &[0b100], &mut keys);
+        assert!(keys[(10 + 2) * 2]);
+        assert_eq!(acks(), Some(4));
+        send(FrameKind::Changes, 5, &[0x02], &mut keys);
+        assert!(!keys[(10 + 2) * 2]);
+        assert_eq!(acks(), Some(5));
+        //the resend after a lost ack gets acked again but not applied a second time
+        keys.set((10 + 2) * 2, true);
+        send(FrameKind::Changes, 5, &[0x02], &mut keys);
+        assert!(keys[(10 + 2) * 2]);
+        assert_eq!(acks(), Some(5));
+        //a frame after a gap waits for the state
+        send(FrameKind::Changes, 7, &[0x81], &mut keys);
+        assert!(!keys[(10 + 1) * 2]);
+        assert_eq!(acks(), None);
+    }
+
+    #[test]
+    fn heartbeat_timeout() {
+        let (up, down) = (Wire::new(0, 0), Wire::new(0, 0));
+        let mut sender: SplitSender<_, _, 8> = SplitSender::new(End(&down), End(&up));
+        let mut matrix: SplitMatrix<_, _, 8> = SplitMatrix::new(End(&up), End(&down), 0);
+        let mut local = BitArray::ZERO;
+        let mut primary = BitArray::ZERO;
+        local.set(3 * 2, true);
+        assert!(!matrix.is_connected());
+        for _ in 0..10 {
+            sender.poll(&local);
+            matrix.scan(&mut primary);
+        }
+        assert!(matrix.is_connected() && primary[3 * 2]);
+        //the heartbeat keeps an idle link up
+        for _ in 0..LINK_TIMEOUT * 3 {
+            sender.poll(&local);
+            matrix.scan(&mut primary);
+        }
+        assert!(matrix.is_connected() && primary[3 * 2]);
+        //the cable gets pulled, the keys of the other half come up once the last frame is
+        //LINK_TIMEOUT old
+        primary.set(3 * 2 + 1, false);
+        for _ in 0..LINK_TIMEOUT - HEARTBEAT {
+            matrix.scan(&mut primary);
+        }
+        assert!(matrix.is_connected() && primary[3 * 2]);
+        for _ in 0..HEARTBEAT {
+            matrix.scan(&mut primary);
+        }
+        assert!(!matrix.is_connected() && !primary[3 * 2] && primary[3 * 2 + 1]);
+        //and go down again with the state once it is back
+        up.borrow_mut().bytes.clear();
+        for _ in 0..HEARTBEAT * 2 {
+            sender.poll(&local);
+            matrix.scan(&mut primary);
+        }
+        assert!(matrix.is_connected() && primary[3 * 2]);
+    }
+
+    #[test]
+    fn noisy_link() {
+        //bits flipped and bytes lost in both directions
+        for (flip_up, drop_up, flip_down, drop_down) in
+            [(0, 0, 0, 0), (13, 0, 5, 0), (0, 17, 0, 7), (11, 29, 7, 23)]
+        {
+            let (up, down) = (Wire::new(flip_up, drop_up), Wire::new(flip_down, drop_down));
+            let mut sender: SplitSender<_, _, 42> = SplitSender::new(End(&down), End(&up));
+            let mut matrix: SplitMatrix<_, _, 42> = SplitMatrix::new(End(&up), End(&down), 49);
+            let mut local: BitArr!(for 256) = BitArray::ZERO;
+            let mut primary: BitArr!(for 256) = BitArray::ZERO;
+            let mut seed = 54321u32;
+            for tick in 0..20_000 {
+                if tick % 7 == 0 && tick < 15_000 {
+                    seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                    let key = (seed >> 16) as usize % 42;
+                    let pressed = !local[key * 2];
+                    local.set(key * 2, pressed);
+                }
+                sender.poll(&local);
+                matrix.scan(&mut primary);
+                //nothing lands outside of the offset
+                assert!(primary[..49 * 2].not_any());
+            }
+            for key in 0..42 {
+                assert_eq!(local[key * 2], primary[(49 + key) * 2], "key {}", key);
+            }
+            assert!(matrix.is_connected());
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::eeprom::Eeprom;
+use crate::keymap::Keymap;
+use crate::macros::MacroStore;
+use crate::text_expansion::TextExpansion;
+use crate::vendor::{Region, VendorTarget};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+//Everything the host can change over the vendor protocol. Commits only touch the copies in
+//RAM, writing them to the EEPROM is left to the main loop since it takes a while
+pub struct Storage {
+    pub text_expansion: TextExpansion,
+    pub macros: MacroStore,
+    pub keymap: Keymap,
+    unsaved: [bool; 3],
+}
+
+impl Storage {
+    pub fn new() -> Self {
+        Self {
+            text_expansion: TextExpansion::new(),
+            macros: MacroStore::new(),
+            keymap: Keymap::new(),
+            unsaved: [false; 3],
+        }
+    }
+    pub fn load<I2C, E>(&mut self, eeprom: &mut Eeprom<I2C>) -> Result<(), E>
+    where
+        I2C: Write<Error = E> + WriteRead<Error = E>,
+    {
+        self.text_expansion.load(eeprom)?;
+        self.macros.load(eeprom)?;
+        self.keymap.load(eeprom)
+    }
+    //a failed write isn't retried, the upload has to be repeated
+    pub fn save_pending<I2C, E>(&mut self, eeprom: &mut Eeprom<I2C>) -> Result<(), E>
+    where
+        I2C: Write<Error = E> + WriteRead<Error = E>,
+    {
+        if self.unsaved[Region::TextExpansion as usize] {
+            self.unsaved[Region::TextExpansion as usize] = false;
+            self.text_expansion.save(eeprom)?;
+        }
+        if self.unsaved[Region::Macros as usize] {
+            self.unsaved[Region::Macros as usize] = false;
+            self.macros.save(eeprom)?;
+        }
+        if self.unsaved[Region::Keymap as usize] {
+            self.unsaved[Region::Keymap as usize] = false;
+            self.keymap.save(eeprom)?;
+        }
+        Ok(())
+    }
+}
+
+impl VendorTarget for Storage {
+    fn region_len(&self, region: Region) -> usize {
+        match region {
+            Region::TextExpansion => self.text_expansion.raw().len(),
+            Region::Macros => self.macros.raw().len(),
+            Region::Keymap => self.keymap.encoded_len(),
+        }
+    }
+    fn read(&self, region: Region, offset: usize, buffer: &mut [u8]) -> usize {
+        let mut read = 0;
+        let mut copy = |data: &mut dyn Iterator<Item = u8>| {
+            for (dst, src) in buffer.iter_mut().zip(data.skip(offset)) {
+                *dst = src;
+                read += 1;
+            }
+        };
+        match region {
+            Region::TextExpansion => copy(&mut self.text_expansion.raw().iter().copied()),
+            Region::Macros => copy(&mut self.macros.raw().iter().copied()),
+            Region::Keymap => copy(&mut self.keymap.encoded()),
+        }
+        read
+    }
+    fn commit(&mut self, region: Region, data: &[u8]) -> bool {
+        let accepted = match region {
+            Region::TextExpansion => self.text_expansion.set_raw(data).is_ok(),
+            Region::Macros => self.macros.set_raw(data),
+            Region::Keymap => self.keymap.set_encoded(data),
+        };
+        self.unsaved[region as usize] |= accepted;
+        accepted
+    }
+}
@@ -0,0 +1,561 @@
+use crate::keyboard_layouts::Action;
+use crate::keycodes::KeyCode;
+use heapless::Vec;
+
+//key events that can wait for a tap-hold decision
+const QUEUE_LEN: usize = 16;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    //only the tapping term decides, other keys don't matter
+    TappingTerm,
+    //hold once another key is pressed and released while the tap-hold key is down
+    PermissiveHold,
+    //hold as soon as another key is pressed
+    HoldOnOtherKeyPress,
+}
+
+#[derive(Clone, Copy)]
+pub struct TapHoldConfig {
+    //ms after which the key counts as held
+    pub tapping_term: u16,
+    //pressing the key again within this many ms of a tap taps it again, holding it then
+    //repeats the tap key instead of activating the hold, 0 disables it
+    pub quick_tap_term: u16,
+    pub flavor: Flavor,
+    //a hold that ended without another key being pressed sends the tap
+    pub retro_tap: bool,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum Hold {
+    Modifier(KeyCode),
+    //momentarily activates the layer
+    Layer(u8),
+}
+
+#[derive(Clone, Copy)]
+pub struct TapHold {
+    pub tap: KeyCode,
+    pub hold: Hold,
+    pub config: TapHoldConfig,
+}
+
+//What a tap dance does after n taps, the last one either released in time or held
+#[derive(Clone, Copy)]
+pub struct DanceStep {
+    pub tap: Action,
+    pub hold: Action,
+}
+
+#[derive(Clone, Copy)]
+pub struct TapDance {
+    //ms the next tap may take, a tap held this long is a hold
+    pub term: u16,
+    //steps[n - 1] for n taps, more taps than steps repeat the last one
+    pub steps: &'static [DanceStep],
+}
+
+//Keys whose press has to wait for a decision
+#[derive(Clone, Copy)]
+pub enum Behavior {
+    TapHold(TapHoldConfig),
+    //term and the tap count at which the dance ends right away
+    TapDance(u16, u8),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    //not a tap-hold key
+    Plain,
+    Tap,
+    Hold,
+    //tap count and whether the last tap was held
+    Dance(u8, bool),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Resolved {
+    pub key: u8,
+    pub pressed: bool,
+    pub role: Role,
+}
+
+#[derive(Clone, Copy)]
+struct KeyEvent {
+    key: u8,
+    pressed: bool,
+    time: u32,
+}
+
+//tap-hold key that is down and already decided
+struct Active {
+    key: u8,
+    role: Role,
+    retro_tap: bool,
+    interrupted: bool,
+}
+
+//Sits between the matrix and the actions. Key events queue up behind an undecided tap-hold
+//or tap dance key and come out in their original order once it is decided. Times are only taken from the
+//events and the now passed to next(), so a recorded event sequence always resolves the same
+pub struct TapHoldResolver {
+    queue: Vec<KeyEvent, QUEUE_LEN>,
+    active: Vec<Active, 8>,
+    //key and release time of the last tap
+    last_tap: Option<(u8, u32)>,
+    //sent before the queue, the retro tap after a hold and the release of a finished dance
+    extra: Vec<Resolved, 2>,
+}
+
+impl TapHoldResolver {
+    pub fn new() -> Self {
+        Self {
+            queue: Vec::new(),
+            active: Vec::new(),
+            last_tap: None,
+            extra: Vec::new(),
+        }
+    }
+    //returns false if the queue is full, the event has to be fed again later
+    pub fn event(&mut self, key: u8, pressed: bool, time: u32) -> bool {
+        self.queue.push(KeyEvent { key, pressed, time }).is_ok()
+    }
+    //Next event in order, None while the first queued key is an undecided tap-hold key.
+    //The lookup gives the behavior of a key if its current action has to wait, it is only
+    //asked once the events before have been handled, so layer changes apply.
+    pub fn next<F>(&mut self, now: u32, lookup: F) -> Option<Resolved>
+    where
+        F: Fn(u8) -> Option<Behavior>,
+    {
+        if !self.extra.is_empty() {
+            return Some(self.extra.remove(0));
+        }
+        let event = *self.queue.first()?;
+        if !event.pressed {
+            self.queue.remove(0);
+            let role = match self.active.iter().position(|a| a.key == event.key) {
+                Some(i) => {
+                    let active = self.active.swap_remove(i);
+                    if active.role == Role::Tap {
+                        self.last_tap = Some((event.key, event.time));
+                    } else if active.retro_tap && !active.interrupted {
+                        for pressed in [true, false] {
+                            let _ = self.extra.push(Resolved {
+                                key: event.key,
+                                pressed,
+                                role: Role::Tap,
+                            });
+                        }
+                        self.last_tap = Some((event.key, event.time));
+                    }
+                    active.role
+                }
+                None => Role::Plain,
+            };
+            return Some(Resolved {
+                key: event.key,
+                pressed: false,
+                role,
+            });
+        }
+        let mut retro_tap = false;
+        //false once the events up to the release of the key have been used up
+        let mut down = true;
+        let role = match lookup(event.key) {
+            Some(Behavior::TapHold(config)) => {
+                retro_tap = config.retro_tap;
+                let role = self.decide(event, config, now)?;
+                self.queue.remove(0);
+                role
+            }
+            Some(Behavior::TapDance(term, taps)) => {
+                let (role, end, released) = self.dance(event, term, taps, now)?;
+                //the taps are used up, the events of other keys in between stay
+                let mut i = 0;
+                self.queue.retain(|e| {
+                    i += 1;
+                    i > end || e.key != event.key
+                });
+                down = !released;
+                role
+            }
+            None => {
+                self.queue.remove(0);
+                Role::Plain
+            }
+        };
+        for active in self.active.iter_mut() {
+            active.interrupted = true;
+        }
+        if role != Role::Plain {
+            if down {
+                //a key can't be pressed twice, this only fails with more than 8 held keys
+                let _ = self.active.push(Active {
+                    key: event.key,
+                    role,
+                    retro_tap,
+                    interrupted: false,
+                });
+            } else {
+                let _ = self.extra.push(Resolved {
+                    key: event.key,
+                    pressed: false,
+                    role,
+                });
+            }
+        }
+        Some(Resolved {
+            key: event.key,
+            pressed: true,
+            role,
+        })
+    }
+    fn decide(&self, press: KeyEvent, config: TapHoldConfig, now: u32) -> Option<Role> {
+        if let Some((key, released)) = self.last_tap {
+            if key == press.key && press.time.wrapping_sub(released) < config.quick_tap_term as u32
+            {
+                return Some(Role::Tap);
+            }
+        }
+        let term = config.tapping_term as u32;
+        let mut pressed_after: Vec<u8, QUEUE_LEN> = Vec::new();
+        for later in &self.queue[1..] {
+            //the term ran out before this event happened
+            if later.time.wrapping_sub(press.time) >= term {
+                return Some(Role::Hold);
+            }
+            if later.key == press.key {
+                return Some(Role::Tap);
+            }
+            if later.pressed {
+                if config.flavor == Flavor::HoldOnOtherKeyPress {
+                    return Some(Role::Hold);
+                }
+                let _ = pressed_after.push(later.key);
+            } else if config.flavor == Flavor::PermissiveHold && pressed_after.contains(&later.key)
+            {
+                return Some(Role::Hold);
+            }
+        }
+        if now.wrapping_sub(press.time) >= term || self.queue.is_full() {
+            Some(Role::Hold)
+        } else {
+            None
+        }
+    }
+    //Returns the outcome, the number of queued events it took and whether the key was
+    //released in them. A dance ends when the term runs out after a tap or with the key held,
+    //when the last step is reached or with the press of another key.
+    fn dance(&self, press: KeyEvent, term: u16, taps: u8, now: u32) -> Option<(Role, usize, bool)> {
+        let term = term as u32;
+        let mut count = 1u8;
+        let mut down = true;
+        let mut last = press.time;
+        for (i, later) in self.queue.iter().enumerate().skip(1) {
+            if later.time.wrapping_sub(last) >= term {
+                return Some((Role::Dance(count, down), i, !down));
+            }
+            if later.key == press.key {
+                down = later.pressed;
+                last = later.time;
+                if down {
+                    count = count.saturating_add(1);
+                } else if count >= taps {
+                    return Some((Role::Dance(count, false), i + 1, true));
+                }
+            } else if later.pressed {
+                //interrupted, counts as tap even if the key is still down
+                return Some((Role::Dance(count, false), i, !down));
+            }
+        }
+        if now.wrapping_sub(last) >= term || self.queue.is_full() {
+            Some((Role::Dance(count, down), self.queue.len(), !down))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Role::*;
+
+    const TERM: u16 = 200;
+    const QUICK_TAP: u16 = 150;
+
+    fn config(flavor: Flavor, retro_tap: bool) -> TapHoldConfig {
+        TapHoldConfig {
+            tapping_term: TERM,
+            quick_tap_term: QUICK_TAP,
+            flavor,
+            retro_tap,
+        }
+    }
+
+    //Replays (key, pressed, time) one ms at a time like the scan does, keys below mod_taps
+    //are tap-hold keys. Returns what came out as (key, pressed, role).
+    fn replay(
+        config: TapHoldConfig,
+        mod_taps: u8,
+        events: &[(u8, bool, u32)],
+    ) -> Vec<(u8, bool, Role), 32> {
+        let lookup = |key: u8| (key < mod_taps).then_some(Behavior::TapHold(config));
+        let mut resolver = TapHoldResolver::new();
+        let mut resolved = Vec::new();
+        let end = events.last().map_or(0, |event| event.2) + TERM as u32 * 2;
+        let mut events = events.iter().peekable();
+        for now in 0..=end {
+            while let Some((key, pressed, _)) = events.next_if(|event| event.2 == now) {
+                assert!(resolver.event(*key, *pressed, now));
+            }
+            while let Some(event) = resolver.next(now, lookup) {
+                resolved
+                    .push((event.key, event.pressed, event.role))
+                    .unwrap();
+            }
+        }
+        resolved
+    }
+
+    #[test]
+    fn tapping_term() {
+        let flavor = config(Flavor::TappingTerm, false);
+        let tap = [(0, true, 10), (0, false, 100)];
+        assert_eq!(replay(flavor, 1, &tap), [(0, true, Tap), (0, false, Tap)]);
+        let held = [(0, true, 10), (0, false, 300)];
+        assert_eq!(
+            replay(flavor, 1, &held),
+            [(0, true, Hold), (0, false, Hold)]
+        );
+        //another key inside the term doesn't decide anything
+        let nested = [
+            (0, true, 10),
+            (1, true, 50),
+            (1, false, 80),
+            (0, false, 120),
+        ];
+        assert_eq!(
+            replay(flavor, 1, &nested),
+            [
+                (0, true, Tap),
+                (1, true, Plain),
+                (1, false, Plain),
+                (0, false, Tap)
+            ]
+        );
+        //but its press after the term does, and it comes out after the hold
+        let late = [
+            (0, true, 10),
+            (1, true, 250),
+            (1, false, 280),
+            (0, false, 300),
+        ];
+        assert_eq!(
+            replay(flavor, 1, &late),
+            [
+                (0, true, Hold),
+                (1, true, Plain),
+                (1, false, Plain),
+                (0, false, Hold)
+            ]
+        );
+    }
+
+    #[test]
+    fn permissive_hold() {
+        let flavor = config(Flavor::PermissiveHold, false);
+        let nested = [
+            (0, true, 10),
+            (1, true, 50),
+            (1, false, 80),
+            (0, false, 120),
+        ];
+        assert_eq!(
+            replay(flavor, 1, &nested),
+            [
+                (0, true, Hold),
+                (1, true, Plain),
+                (1, false, Plain),
+                (0, false, Hold)
+            ]
+        );
+        let rolled = [
+            (0, true, 10),
+            (1, true, 50),
+            (0, false, 80),
+            (1, false, 120),
+        ];
+        assert_eq!(
+            replay(flavor, 1, &rolled),
+            [
+                (0, true, Tap),
+                (1, true, Plain),
+                (0, false, Tap),
+                (1, false, Plain)
+            ]
+        );
+    }
+
+    #[test]
+    fn hold_on_other_key_press() {
+        let flavor = config(Flavor::HoldOnOtherKeyPress, false);
+        let rolled = [
+            (0, true, 10),
+            (1, true, 50),
+            (0, false, 80),
+            (1, false, 120),
+        ];
+        assert_eq!(
+            replay(flavor, 1, &rolled),
+            [
+                (0, true, Hold),
+                (1, true, Plain),
+                (0, false, Hold),
+                (1, false, Plain)
+            ]
+        );
+        let tap = [(0, true, 10), (0, false, 100)];
+        assert_eq!(replay(flavor, 1, &tap), [(0, true, Tap), (0, false, Tap)]);
+    }
+
+    #[test]
+    fn retro_tap() {
+        let flavor = config(Flavor::TappingTerm, true);
+        let held = [(0, true, 10), (0, false, 300)];
+        assert_eq!(
+            replay(flavor, 1, &held),
+            [
+                (0, true, Hold),
+                (0, false, Hold),
+                (0, true, Tap),
+                (0, false, Tap)
+            ]
+        );
+        //not after another key was pressed during the hold
+        let used = [
+            (0, true, 10),
+            (1, true, 250),
+            (1, false, 280),
+            (0, false, 300),
+        ];
+        assert_eq!(
+            replay(flavor, 1, &used),
+            [
+                (0, true, Hold),
+                (1, true, Plain),
+                (1, false, Plain),
+                (0, false, Hold)
+            ]
+        );
+    }
+
+    #[test]
+    fn quick_tap_term() {
+        let flavor = config(Flavor::TappingTerm, false);
+        //pressed again soon after a tap, holding it repeats the tap
+        let quick = [
+            (0, true, 10),
+            (0, false, 50),
+            (0, true, 100),
+            (0, false, 500),
+        ];
+        assert_eq!(
+            replay(flavor, 1, &quick),
+            [
+                (0, true, Tap),
+                (0, false, Tap),
+                (0, true, Tap),
+                (0, false, Tap)
+            ]
+        );
+        let slow = [
+            (0, true, 10),
+            (0, false, 50),
+            (0, true, 250),
+            (0, false, 600),
+        ];
+        assert_eq!(
+            replay(flavor, 1, &slow),
+            [
+                (0, true, Tap),
+                (0, false, Tap),
+                (0, true, Hold),
+                (0, false, Hold)
+            ]
+        );
+        //a hold doesn't count as the tap before
+        let after_hold = [
+            (0, true, 10),
+            (0, false, 300),
+            (0, true, 350),
+            (0, false, 700),
+        ];
+        assert_eq!(
+            replay(flavor, 1, &after_hold),
+            [
+                (0, true, Hold),
+                (0, false, Hold),
+                (0, true, Hold),
+                (0, false, Hold)
+            ]
+        );
+    }
+
+    #[test]
+    fn rolled_mod_taps() {
+        //typing fast over two mod-taps gives both taps in order
+        let rolled = [
+            (0, true, 10),
+            (1, true, 50),
+            (0, false, 80),
+            (1, false, 120),
+        ];
+        for flavor in [Flavor::TappingTerm, Flavor::PermissiveHold] {
+            assert_eq!(
+                replay(config(flavor, false), 2, &rolled),
+                [
+                    (0, true, Tap),
+                    (1, true, Tap),
+                    (0, false, Tap),
+                    (1, false, Tap)
+                ]
+            );
+        }
+        let flavor = config(Flavor::PermissiveHold, false);
+        let nested = [
+            (0, true, 10),
+            (1, true, 50),
+            (1, false, 80),
+            (0, false, 120),
+        ];
+        assert_eq!(
+            replay(flavor, 2, &nested),
+            [
+                (0, true, Hold),
+                (1, true, Tap),
+                (1, false, Tap),
+                (0, false, Hold)
+            ]
+        );
+        //both held past the term
+        let held = [
+            (0, true, 10),
+            (1, true, 50),
+            (1, false, 400),
+            (0, false, 450),
+        ];
+        assert_eq!(
+            replay(flavor, 2, &held),
+            [
+                (0, true, Hold),
+                (1, true, Hold),
+                (1, false, Hold),
+                (0, false, Hold)
+            ]
+        );
+    }
+}
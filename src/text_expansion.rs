@@ -0,0 +1,138 @@
+use crate::eeprom::{Eeprom, BLOB_HEADER_LEN, EXPANSION_ADDRESS, EXPANSION_SIZE};
+use crate::host_layouts::HostLayout;
+use crate::keycodes::{KeyCode, Modifiers};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use heapless::Vec;
+
+pub const MAX_ABBREVIATION: usize = 16;
+pub const MAX_EXPANSION: usize = 128;
+const MAGIC: [u8; 2] = *b"TX";
+
+#[derive(Debug)]
+pub enum TableError {
+    TableFull,
+    Malformed,
+}
+
+//Watches the typed characters for abbreviations, the trigger table is a copy of the one in
+//the EEPROM. Entries are stored as [abbreviation len][expansion len][abbreviation][expansion]
+pub struct TextExpansion {
+    table: Vec<u8, { EXPANSION_SIZE - BLOB_HEADER_LEN }>,
+    typed: Vec<char, MAX_ABBREVIATION>,
+}
+
+impl TextExpansion {
+    pub fn new() -> Self {
+        Self {
+            table: Vec::new(),
+            typed: Vec::new(),
+        }
+    }
+    //raw table as stored in the EEPROM, used for transfers to the host
+    pub fn raw(&self) -> &[u8] {
+        &self.table
+    }
+    pub fn set_raw(&mut self, data: &[u8]) -> Result<(), TableError> {
+        if !is_valid(data) {
+            return Err(TableError::Malformed);
+        }
+        self.table.clear();
+        self.table
+            .extend_from_slice(data)
+            .map_err(|_| TableError::TableFull)?;
+        self.typed.clear();
+        Ok(())
+    }
+    pub fn load<I2C, E>(&mut self, eeprom: &mut Eeprom<I2C>) -> Result<(), E>
+    where
+        I2C: Write<Error = E> + WriteRead<Error = E>,
+    {
+        eeprom.read_blob(EXPANSION_ADDRESS, MAGIC, &mut self.table)?;
+        if !is_valid(&self.table) {
+            self.table.clear();
+        }
+        Ok(())
+    }
+    pub fn save<I2C, E>(&self, eeprom: &mut Eeprom<I2C>) -> Result<(), E>
+    where
+        I2C: Write<Error = E> + WriteRead<Error = E>,
+    {
+        eeprom.write_blob(EXPANSION_ADDRESS, MAGIC, &self.table)
+    }
+    //Feeds a live key press, returns the number of backspaces and the expansion to type
+    //once the typed characters end with an abbreviation
+    pub fn key_pressed(
+        &mut self,
+        key: KeyCode,
+        modifiers: Modifiers,
+        layout: HostLayout,
+    ) -> Option<(usize, &str)> {
+        if key == KeyCode::Backspace && modifiers.is_empty() {
+            self.typed.pop();
+            return None;
+        }
+        match layout.character(key, modifiers) {
+            Some(c) => {
+                if self.typed.is_full() {
+                    self.typed.remove(0);
+                }
+                let _ = self.typed.push(c);
+            }
+            //cursor movement, shortcuts, etc. end the word
+            None => {
+                self.typed.clear();
+                return None;
+            }
+        }
+        let typed = &self.typed;
+        let (abbreviation, expansion) = entries(&self.table).find(|(abbreviation, _)| {
+            let len = abbreviation.chars().count();
+            len <= typed.len()
+                && abbreviation
+                    .chars()
+                    .zip(typed[typed.len() - len..].iter())
+                    .all(|(a, b)| a == *b)
+        })?;
+        let backspaces = abbreviation.chars().count();
+        self.typed.clear();
+        Some((backspaces, expansion))
+    }
+    pub fn reset(&mut self) {
+        self.typed.clear();
+    }
+}
+
+fn entries(table: &[u8]) -> impl Iterator<Item = (&str, &str)> {
+    let mut rest = table;
+    core::iter::from_fn(move || {
+        let (abbreviation, expansion, tail) = split_entry(rest)?;
+        rest = tail;
+        Some((abbreviation, expansion))
+    })
+}
+fn is_valid(mut table: &[u8]) -> bool {
+    while !table.is_empty() {
+        match split_entry(table) {
+            Some((abbreviation, expansion, tail))
+                if !abbreviation.is_empty()
+                    && abbreviation.chars().count() <= MAX_ABBREVIATION
+                    && expansion.len() <= MAX_EXPANSION =>
+            {
+                table = tail
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+fn split_entry(data: &[u8]) -> Option<(&str, &str, &[u8])> {
+    let abbreviation_len = *data.first()? as usize;
+    let expansion_len = *data.get(1)? as usize;
+    let end = 2 + abbreviation_len + expansion_len;
+    if data.len() < end {
+        return None;
+    }
+    let abbreviation = core::str::from_utf8(&data[2..2 + abbreviation_len]).ok()?;
+    let expansion = core::str::from_utf8(&data[2 + abbreviation_len..end]).ok()?;
+    Some((abbreviation, expansion, &data[end..]))
+}
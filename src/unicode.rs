@@ -0,0 +1,124 @@
+use crate::host_layouts::{HostLayout, KeyStroke};
+use crate::keycodes::{KeyCode, KeyCode::*, Modifiers};
+use heapless::Vec;
+
+//How the host OS accepts code points it has no key for
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeMode {
+    Disabled,
+    //IBus/GTK: Ctrl+Shift+U, hex digits, Space
+    Linux,
+    //Alt held, numpad plus, hex digits, needs EnableHexNumpad set in the registry
+    WinNumpad,
+    //WinCompose with its default compose key (right alt): compose, u, hex digits, Enter
+    WinCompose,
+    //"Unicode Hex Input" input source: Option held, utf-16 units as 4 hex digits each
+    MacOs,
+}
+
+impl UnicodeMode {
+    pub const ALL: [Self; 5] = [
+        Self::Disabled,
+        Self::Linux,
+        Self::WinNumpad,
+        Self::WinCompose,
+        Self::MacOs,
+    ];
+
+    pub fn from_u8(mode: u8) -> Option<Self> {
+        Self::ALL.get(mode as usize).copied()
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Disabled => "off",
+            Self::Linux => "Linux",
+            Self::WinNumpad => "Win numpad",
+            Self::WinCompose => "WinCompose",
+            Self::MacOs => "macOS",
+        }
+    }
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+pub type InputSequence = Vec<KeyStroke, 12>;
+
+const fn stroke(key: KeyCode, modifiers: Modifiers) -> KeyStroke {
+    KeyStroke { key, modifiers }
+}
+//pseudo stroke that only brings the modifiers back up, it sends no key
+const RELEASE_MODIFIERS: KeyStroke = stroke(No, Modifiers::NONE);
+
+const KEYPAD: [KeyCode; 10] = [Kp0, Kp1, Kp2, Kp3, Kp4, Kp5, Kp6, Kp7, Kp8, Kp9];
+const US_HEX: [KeyCode; 16] = [N0, N1, N2, N3, N4, N5, N6, N7, N8, N9, A, B, C, D, E, F];
+
+//returns an empty sequence if the code point can't be entered in this mode
+pub fn input_sequence(mode: UnicodeMode, layout: HostLayout, c: char) -> InputSequence {
+    entry_sequence(mode, layout, c).unwrap_or_default()
+}
+
+//None if the host layout lacks a key of the sequence, leaving it out would enter another code
+//point
+fn entry_sequence(mode: UnicodeMode, layout: HostLayout, c: char) -> Option<InputSequence> {
+    let mut sequence = InputSequence::new();
+    let code = c as u32;
+    //a key behind a dead key would put the accent into the sequence as well
+    let key = |c: char| match layout.lookup(c) {
+        Some((None, stroke)) => Some(stroke),
+        _ => None,
+    };
+    let hex = |digit: u32| key(char::from_digit(digit, 16).unwrap());
+    match mode {
+        UnicodeMode::Disabled => return None,
+        UnicodeMode::Linux => {
+            let u = key('u')?;
+            let _ = sequence.push(stroke(u.key, Modifiers::LCTRL | Modifiers::LSHIFT));
+            for digit in hex_digits(code) {
+                let _ = sequence.push(hex(digit)?);
+            }
+            let _ = sequence.push(stroke(Space, Modifiers::NONE));
+        }
+        UnicodeMode::WinNumpad => {
+            if code > 0xFFFF {
+                return None;
+            }
+            let _ = sequence.push(stroke(KpPlus, Modifiers::LALT));
+            for digit in hex_digits(code) {
+                let key = match digit {
+                    0..=9 => KEYPAD[digit as usize],
+                    _ => hex(digit)?.key,
+                };
+                let _ = sequence.push(stroke(key, Modifiers::LALT));
+            }
+            let _ = sequence.push(RELEASE_MODIFIERS);
+        }
+        UnicodeMode::WinCompose => {
+            let u = key('u')?;
+            let _ = sequence.push(stroke(RAlt, Modifiers::NONE));
+            let _ = sequence.push(u);
+            for digit in hex_digits(code) {
+                let _ = sequence.push(hex(digit)?);
+            }
+            let _ = sequence.push(stroke(Enter, Modifiers::NONE));
+        }
+        UnicodeMode::MacOs => {
+            let mut units = [0u16; 2];
+            //the input source has its own QWERTY based layout
+            for unit in c.encode_utf16(&mut units) {
+                for shift in (0..4).rev() {
+                    let digit = (*unit >> (shift * 4)) & 0xF;
+                    let _ = sequence.push(stroke(US_HEX[digit as usize], Modifiers::LALT));
+                }
+            }
+            let _ = sequence.push(RELEASE_MODIFIERS);
+        }
+    }
+    Some(sequence)
+}
+
+//hex digits of the code point without leading zeros, most significant first
+fn hex_digits(code: u32) -> impl Iterator<Item = u32> {
+    let len = (8 - code.leading_zeros() as usize / 4).max(1);
+    (0..len).rev().map(move |i| (code >> (i * 4)) & 0xF)
+}
@@ -0,0 +1,284 @@
+use crate::crc::crc8;
+use heapless::Vec;
+
+//Vendor protocol on top of the PS/2 host to device channel. The host opens a session by
+//sending 0xE2 'I' 'K' 'B' as commands, 0xE2 is unused by the keyboard command set and
+//the sequence isn't something a normal i8042 driver sends. Inside the session both
+//directions use frames of [SYNC][payload len][command][payload][crc8 over len..payload],
+//responses carry the command with the top bit set and a status byte as first payload byte.
+//The frames are arbitrary bytes, the host has to pass them on untranslated (i8042.direct=1 on
+//Linux), the set 1 translation of the i8042 would rewrite them and swallow the 0xF0 bytes.
+pub const UNLOCK: [u8; 4] = [0xE2, b'I', b'K', b'B'];
+pub const SYNC: u8 = 0xA5;
+pub const MAX_PAYLOAD: usize = 16;
+pub const PROTOCOL_VERSION: u8 = 1;
+//the session closes when the host goes quiet for this many ms
+pub const SESSION_TIMEOUT: u32 = 2000;
+pub const RESPONSE: u8 = 0x80;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Command {
+    //-> [protocol version, max payload, region count]
+    Info = 0x01,
+    //[region] -> [len lo, len hi]
+    Size = 0x02,
+    //[region, offset lo, offset hi, len] -> [data]
+    Read = 0x03,
+    //[region], starts collecting a new image for the region
+    BeginWrite = 0x04,
+    //[offset lo, offset hi, data]
+    Write = 0x05,
+    //hands the collected image to the keyboard, which checks and saves it
+    Commit = 0x06,
+    Close = 0x07,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Status {
+    Ok = 0,
+    BadCrc = 1,
+    UnknownCommand = 2,
+    BadArgument = 3,
+    Rejected = 4,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Region {
+    TextExpansion = 0,
+    Macros = 1,
+    Keymap = 2,
+}
+pub const REGION_COUNT: u8 = 3;
+
+//Storage the session reads from and commits to
+pub trait VendorTarget {
+    fn region_len(&self, region: Region) -> usize;
+    //returns the number of bytes copied
+    fn read(&self, region: Region, offset: usize, buffer: &mut [u8]) -> usize;
+    //returns false if the image is rejected
+    fn commit(&mut self, region: Region, data: &[u8]) -> bool;
+}
+
+#[derive(PartialEq, Eq)]
+pub enum Received {
+    //normal keyboard command
+    NotVendor,
+    //part of the unlock sequence, acked like any other command
+    Unlocking,
+    Session,
+}
+
+impl Command {
+    pub fn from_u8(command: u8) -> Option<Self> {
+        Some(match command {
+            0x01 => Command::Info,
+            0x02 => Command::Size,
+            0x03 => Command::Read,
+            0x04 => Command::BeginWrite,
+            0x05 => Command::Write,
+            0x06 => Command::Commit,
+            0x07 => Command::Close,
+            _ => return None,
+        })
+    }
+}
+impl Region {
+    pub fn from_u8(region: u8) -> Option<Self> {
+        Some(match region {
+            0 => Region::TextExpansion,
+            1 => Region::Macros,
+            2 => Region::Keymap,
+            _ => return None,
+        })
+    }
+}
+
+pub fn encode_frame<E: Extend<u8>>(command: u8, payload: &[u8], out: &mut E) {
+    let mut frame: Vec<u8, { MAX_PAYLOAD + 3 }> = Vec::new();
+    let _ = frame.push(payload.len() as u8);
+    let _ = frame.push(command);
+    let _ = frame.extend_from_slice(payload);
+    let crc = crc8(&frame);
+    let _ = frame.push(crc);
+    out.extend([SYNC]);
+    out.extend(frame);
+}
+
+//N is the size of the largest region image
+pub struct Session<const N: usize> {
+    unlock_progress: usize,
+    open: bool,
+    last_byte: u32,
+    //frame without the sync byte
+    frame: Vec<u8, { MAX_PAYLOAD + 3 }>,
+    synced: bool,
+    staging_region: Option<Region>,
+    staging: Vec<u8, N>,
+}
+
+impl<const N: usize> Session<N> {
+    pub fn new() -> Self {
+        Self {
+            unlock_progress: 0,
+            open: false,
+            last_byte: 0,
+            frame: Vec::new(),
+            synced: false,
+            staging_region: None,
+            staging: Vec::new(),
+        }
+    }
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+    pub fn poll(&mut self, now: u32) {
+        if self.open && now.wrapping_sub(self.last_byte) > SESSION_TIMEOUT {
+            self.close();
+        }
+    }
+    fn close(&mut self) {
+        self.open = false;
+        self.staging_region = None;
+        self.staging.clear();
+    }
+    pub fn receive<T: VendorTarget, E: Extend<u8>>(
+        &mut self,
+        byte: u8,
+        now: u32,
+        target: &mut T,
+        out: &mut E,
+    ) -> Received {
+        if !self.open {
+            if byte != UNLOCK[self.unlock_progress] {
+                self.unlock_progress = (byte == UNLOCK[0]) as usize;
+                return if self.unlock_progress == 0 {
+                    Received::NotVendor
+                } else {
+                    Received::Unlocking
+                };
+            }
+            self.unlock_progress += 1;
+            if self.unlock_progress == UNLOCK.len() {
+                self.unlock_progress = 0;
+                self.open = true;
+                self.synced = false;
+                self.last_byte = now;
+            }
+            return Received::Unlocking;
+        }
+        self.last_byte = now;
+        if !self.synced {
+            self.synced = byte == SYNC;
+            self.frame.clear();
+            return Received::Session;
+        }
+        let _ = self.frame.push(byte);
+        let len = self.frame[0] as usize;
+        if len > MAX_PAYLOAD {
+            //not a frame, wait for the next sync byte
+            self.synced = false;
+        } else if self.frame.len() == len + 3 {
+            self.synced = false;
+            let command = self.frame[1];
+            if crc8(&self.frame[..len + 2]) != self.frame[len + 2] {
+                encode_frame(command | RESPONSE, &[Status::BadCrc as u8], out);
+            } else {
+                let mut payload: Vec<u8, MAX_PAYLOAD> = Vec::new();
+                let _ = payload.extend_from_slice(&self.frame[2..len + 2]);
+                self.handle(command, &payload, target, out);
+            }
+        }
+        Received::Session
+    }
+    fn handle<T: VendorTarget, E: Extend<u8>>(
+        &mut self,
+        command: u8,
+        payload: &[u8],
+        target: &mut T,
+        out: &mut E,
+    ) {
+        let mut response: Vec<u8, MAX_PAYLOAD> = Vec::new();
+        let status = match Command::from_u8(command) {
+            None => Status::UnknownCommand,
+            Some(Command::Info) => {
+                let _ = response.extend_from_slice(&[
+                    PROTOCOL_VERSION,
+                    MAX_PAYLOAD as u8,
+                    REGION_COUNT,
+                ]);
+                Status::Ok
+            }
+            Some(Command::Size) => match payload.first().and_then(|r| Region::from_u8(*r)) {
+                Some(region) => {
+                    let len = target.region_len(region) as u16;
+                    let _ = response.extend_from_slice(&len.to_le_bytes());
+                    Status::Ok
+                }
+                None => Status::BadArgument,
+            },
+            Some(Command::Read) => match payload {
+                [region, offset_lo, offset_hi, len] if (*len as usize) < MAX_PAYLOAD => {
+                    match Region::from_u8(*region) {
+                        Some(region) => {
+                            let mut data = [0u8; MAX_PAYLOAD - 1];
+                            let offset = u16::from_le_bytes([*offset_lo, *offset_hi]);
+                            let read =
+                                target.read(region, offset as usize, &mut data[..*len as usize]);
+                            let _ = response.extend_from_slice(&data[..read]);
+                            Status::Ok
+                        }
+                        None => Status::BadArgument,
+                    }
+                }
+                _ => Status::BadArgument,
+            },
+            Some(Command::BeginWrite) => match payload.first().and_then(|r| Region::from_u8(*r)) {
+                Some(region) => {
+                    self.staging_region = Some(region);
+                    self.staging.clear();
+                    Status::Ok
+                }
+                None => Status::BadArgument,
+            },
+            Some(Command::Write) => match payload {
+                [offset_lo, offset_hi, data @ ..] if self.staging_region.is_some() => {
+                    let offset = u16::from_le_bytes([*offset_lo, *offset_hi]) as usize;
+                    //chunks have to arrive in order, repeating the last one is fine
+                    if offset > self.staging.len() {
+                        Status::BadArgument
+                    } else {
+                        self.staging.truncate(offset);
+                        match self.staging.extend_from_slice(data) {
+                            Ok(()) => Status::Ok,
+                            Err(_) => Status::Rejected,
+                        }
+                    }
+                }
+                _ => Status::BadArgument,
+            },
+            Some(Command::Commit) => match self.staging_region.take() {
+                Some(region) => {
+                    let accepted = target.commit(region, &self.staging);
+                    self.staging.clear();
+                    if accepted {
+                        Status::Ok
+                    } else {
+                        Status::Rejected
+                    }
+                }
+                None => Status::BadArgument,
+            },
+            Some(Command::Close) => {
+                self.close();
+                Status::Ok
+            }
+        };
+        let mut payload: Vec<u8, MAX_PAYLOAD> = Vec::new();
+        let _ = payload.push(status as u8);
+        let _ = payload.extend_from_slice(&response);
+        encode_frame(command | RESPONSE, &payload, out);
+    }
+}
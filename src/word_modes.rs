@@ -0,0 +1,130 @@
+use crate::keyboard_layouts::Action;
+use crate::keycodes::{KeyCode, Modifiers};
+
+//Caps Word shifts the keys of a single word itself instead of toggling Caps Lock, the lock
+//state of the host (0xED) stays as it is
+#[derive(Clone, Copy)]
+pub struct CapsWordConfig {
+    //ms without a key after which the word ends
+    pub timeout: u16,
+    //typed shifted besides the letters, e.g. minus for the underscore
+    pub shifted: &'static [KeyCode],
+    //typed as they are without ending the word
+    pub continues: &'static [KeyCode],
+}
+
+#[derive(Clone, Copy)]
+pub struct NumWordConfig {
+    pub timeout: u16,
+    //keep the layer besides the digits, modifiers and layer keys
+    pub continues: &'static [KeyCode],
+}
+
+pub struct CapsWord {
+    active: bool,
+    since: u32,
+}
+
+impl CapsWord {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            since: 0,
+        }
+    }
+    pub fn toggle(&mut self, now: u32) {
+        self.active = !self.active;
+        self.since = now;
+    }
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+    pub fn cancel(&mut self) {
+        self.active = false;
+    }
+    pub fn poll(&mut self, now: u32, config: &CapsWordConfig) {
+        if self.active && now.wrapping_sub(self.since) >= config.timeout as u32 {
+            self.active = false;
+        }
+    }
+    //returns true if the key has to be shifted, a word breaking key ends Caps Word
+    pub fn key(
+        &mut self,
+        code: KeyCode,
+        modifiers: Modifiers,
+        now: u32,
+        config: &CapsWordConfig,
+    ) -> bool {
+        if !self.active {
+            return false;
+        }
+        //shortcuts end the word
+        if modifiers.intersects(!(Modifiers::LSHIFT | Modifiers::RSHIFT)) {
+            self.active = false;
+            return false;
+        }
+        self.since = now;
+        let letter = (KeyCode::A as u8..=KeyCode::Z as u8).contains(&(code as u8));
+        if letter || config.shifted.contains(&code) {
+            true
+        } else {
+            self.active = config.continues.contains(&code);
+            false
+        }
+    }
+}
+
+pub struct NumWord {
+    layer: Option<u8>,
+    since: u32,
+}
+
+impl NumWord {
+    pub fn new() -> Self {
+        Self {
+            layer: None,
+            since: 0,
+        }
+    }
+    pub fn toggle(&mut self, layer: u8, now: u32) {
+        self.layer = match self.layer {
+            Some(active) if active == layer => None,
+            _ => Some(layer),
+        };
+        self.since = now;
+    }
+    pub fn layers(&self) -> u8 {
+        self.layer.map_or(0, |layer| 1 << layer)
+    }
+    pub fn poll(&mut self, now: u32, config: &NumWordConfig) {
+        if self.layer.is_some() && now.wrapping_sub(self.since) >= config.timeout as u32 {
+            self.layer = None;
+        }
+    }
+    //called after a key pressed on the layer, anything but a number ends Num Word
+    pub fn key(&mut self, action: Action, now: u32, config: &NumWordConfig) {
+        if self.layer.is_none() {
+            return;
+        }
+        self.since = now;
+        let continues = match action {
+            Action::Key(code) => {
+                let id = code as u8;
+                (KeyCode::N1 as u8..=KeyCode::N0 as u8).contains(&id)
+                    || (KeyCode::KpSlash as u8..=KeyCode::KpDot as u8).contains(&id)
+                    || config.continues.contains(&code)
+                    || code.is_modifier()
+            }
+            Action::No
+            | Action::Transparent
+            | Action::Layer(_)
+            | Action::OneShotModifiers(_)
+            | Action::OneShotLayer(_)
+            | Action::NumWord(_) => true,
+            _ => false,
+        };
+        if !continues {
+            self.layer = None;
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//Tests of the firmware modules that don't touch the hardware, on the host. The firmware only
+//builds for the MCU, so the modules come in with #[path] like the protocol code of
+//tools/ps2_client.
+#![no_std]
+//only the parts with tests get used
+#![allow(dead_code)]
+
+//key_matrix.rs waits for the diodes with riscv::asm::delay, which only runs on the MCU
+extern crate self as riscv;
+mod asm {
+    pub unsafe fn delay(_cycles: u32) {}
+}
+
+fn get_millis() -> u32 {
+    0
+}
+
+#[path = "../../../src/auto_shift.rs"]
+mod auto_shift;
+#[path = "../../../src/combos.rs"]
+mod combos;
+#[path = "../../../src/crc.rs"]
+mod crc;
+#[path = "../../../src/eeprom.rs"]
+mod eeprom;
+#[path = "../../../src/eeprom_map.rs"]
+mod eeprom_map;
+#[path = "../../../src/expander.rs"]
+mod expander;
+#[path = "../../../src/handedness.rs"]
+mod handedness;
+#[path = "../../../src/host_layouts.rs"]
+mod host_layouts;
+#[path = "../../../src/i2c_bus.rs"]
+mod i2c_bus;
+#[path = "../../../src/key_matrix.rs"]
+mod key_matrix;
+#[path = "../../../src/key_overrides.rs"]
+mod key_overrides;
+#[path = "../../../src/keyboard.rs"]
+mod keyboard;
+#[path = "../../../src/keyboard_layouts.rs"]
+mod keyboard_layouts;
+#[path = "../../../src/keycodes.rs"]
+mod keycodes;
+#[path = "../../../src/keymap.rs"]
+mod keymap;
+#[path = "../../../src/layout.rs"]
+mod layout;
+#[path = "../../../src/layout_emulation.rs"]
+mod layout_emulation;
+#[path = "../../../src/leader.rs"]
+mod leader;
+#[path = "../../../src/macros.rs"]
+mod macros;
+#[path = "../../../src/matrix_test.rs"]
+mod matrix_test;
+#[path = "../../../src/menu.rs"]
+mod menu;
+#[path = "../../../src/one_shot.rs"]
+mod one_shot;
+#[allow(clippy::legacy_numeric_constants)]
+#[path = "../../../src/ps2.rs"]
+mod ps2;
+#[path = "../../../src/scancodes.rs"]
+mod scancodes;
+#[path = "../../../src/settings.rs"]
+mod settings;
+#[path = "../../../src/shift_register.rs"]
+mod shift_register;
+#[cfg(feature = "split")]
+#[path = "../../../src/split.rs"]
+mod split;
+#[path = "../../../src/storage.rs"]
+mod storage;
+#[path = "../../../src/tap_hold.rs"]
+mod tap_hold;
+#[path = "../../../src/text_expansion.rs"]
+mod text_expansion;
+#[path = "../../../src/unicode.rs"]
+mod unicode;
+#[path = "../../../src/vendor.rs"]
+mod vendor;
+#[path = "../../../src/word_modes.rs"]
+mod word_modes;
+
+//the debug console of the firmware has nothing to print to here, defined after the modules so
+//they import it like the firmware's one
+#[macro_export]
+macro_rules! sprintln {
+    ($($arg:tt)*) => {{
+        let _ = format_args!($($arg)*);
+    }};
+}
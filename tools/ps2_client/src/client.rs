@@ -0,0 +1,172 @@
+use crate::crc::crc8;
+use crate::transport::Transport;
+use crate::vendor::{encode_frame, Command, Region, Status, MAX_PAYLOAD, RESPONSE, SYNC, UNLOCK};
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(200);
+const RETRIES: usize = 8;
+//payload bytes of a Write after the offset
+const CHUNK: usize = MAX_PAYLOAD - 2;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    NoResponse,
+    Status(Command, Status),
+    Malformed,
+}
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::NoResponse => write!(f, "keyboard doesn't respond"),
+            Error::Status(command, status) => write!(f, "{:?} failed: {:?}", command, status),
+            Error::Malformed => write!(f, "malformed response"),
+        }
+    }
+}
+
+pub struct Info {
+    pub version: u8,
+    pub max_payload: u8,
+    pub regions: u8,
+}
+
+pub struct Client<T: Transport> {
+    transport: T,
+}
+
+impl<T: Transport> Client<T> {
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+    #[cfg(test)]
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+    //the keyboard acks the unlock bytes like normal commands, those acks are skipped
+    pub fn open(&mut self) -> Result<Info, Error> {
+        let mut result = Err(Error::NoResponse);
+        for _ in 0..3 {
+            self.transport.send(&UNLOCK)?;
+            self.drain()?;
+            result = self.info();
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+    pub fn close(&mut self) -> Result<(), Error> {
+        self.request(Command::Close, &[]).map(|_| ())
+    }
+    pub fn info(&mut self) -> Result<Info, Error> {
+        match self.request(Command::Info, &[])?[..] {
+            [version, max_payload, regions] => Ok(Info {
+                version,
+                max_payload,
+                regions,
+            }),
+            _ => Err(Error::Malformed),
+        }
+    }
+    pub fn read(&mut self, region: Region) -> Result<Vec<u8>, Error> {
+        let len = match self.request(Command::Size, &[region as u8])?[..] {
+            [lo, hi] => u16::from_le_bytes([lo, hi]) as usize,
+            _ => return Err(Error::Malformed),
+        };
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            let offset = (data.len() as u16).to_le_bytes();
+            let chunk = (len - data.len()).min(MAX_PAYLOAD - 1) as u8;
+            let response =
+                self.request(Command::Read, &[region as u8, offset[0], offset[1], chunk])?;
+            if response.is_empty() {
+                return Err(Error::Malformed);
+            }
+            data.extend_from_slice(&response);
+        }
+        Ok(data)
+    }
+    pub fn write(&mut self, region: Region, data: &[u8]) -> Result<(), Error> {
+        self.request(Command::BeginWrite, &[region as u8])?;
+        for (i, chunk) in data.chunks(CHUNK).enumerate() {
+            let offset = ((i * CHUNK) as u16).to_le_bytes();
+            let mut payload = vec![offset[0], offset[1]];
+            payload.extend_from_slice(chunk);
+            self.request(Command::Write, &payload)?;
+        }
+        //a retried commit fails if the first one got through, the read back tells
+        match self.request(Command::Commit, &[]) {
+            Ok(_) | Err(Error::Status(_, Status::BadArgument)) => {}
+            Err(error) => return Err(error),
+        }
+        if self.read(region)? != data {
+            return Err(Error::Status(Command::Commit, Status::Rejected));
+        }
+        Ok(())
+    }
+    //sends the frame until an intact response arrives, returns the payload after the status
+    fn request(&mut self, command: Command, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut frame = Vec::new();
+        encode_frame(command as u8, payload, &mut frame);
+        let mut last_status = None;
+        for _ in 0..RETRIES {
+            self.transport.send(&frame)?;
+            match self.response()? {
+                Some((response, data)) if response == command as u8 | RESPONSE => {
+                    match data.first().copied() {
+                        Some(0) => return Ok(data[1..].to_vec()),
+                        //the keyboard got a damaged frame, send it again
+                        Some(1) => last_status = Some(Status::BadCrc),
+                        Some(2) => return Err(Error::Status(command, Status::UnknownCommand)),
+                        Some(3) => return Err(Error::Status(command, Status::BadArgument)),
+                        Some(4) => return Err(Error::Status(command, Status::Rejected)),
+                        _ => return Err(Error::Malformed),
+                    }
+                }
+                //lost, damaged or the answer to a damaged copy of a different frame
+                _ => self.drain()?,
+            }
+        }
+        Err(match last_status {
+            Some(status) => Error::Status(command, status),
+            None => Error::NoResponse,
+        })
+    }
+    fn response(&mut self) -> Result<Option<(u8, Vec<u8>)>, Error> {
+        loop {
+            match self.transport.receive(RESPONSE_TIMEOUT)? {
+                Some(SYNC) => break,
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+        let mut frame = Vec::new();
+        while frame.len() < 2 || frame.len() < frame[0] as usize + 3 {
+            match self.transport.receive(RESPONSE_TIMEOUT)? {
+                Some(byte) => frame.push(byte),
+                None => return Ok(None),
+            }
+            if frame[0] as usize > MAX_PAYLOAD {
+                return Ok(None);
+            }
+        }
+        let (body, crc) = frame.split_at(frame.len() - 1);
+        if crc8(body) != crc[0] {
+            return Ok(None);
+        }
+        Ok(Some((body[1], body[2..].to_vec())))
+    }
+    fn drain(&mut self) -> Result<(), Error> {
+        while self.transport.receive(Duration::from_millis(50))?.is_some() {}
+        Ok(())
+    }
+}
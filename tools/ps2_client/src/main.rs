@@ -0,0 +1,151 @@
+//Reference client for the vendor protocol of the keyboard firmware, see src/vendor.rs
+#[path = "../../../src/crc.rs"]
+mod crc;
+#[path = "../../../src/vendor.rs"]
+#[allow(dead_code)]
+mod vendor;
+
+mod client;
+#[cfg(test)]
+mod simulated;
+mod transport;
+
+use client::Client;
+use std::env;
+use std::fs;
+use std::process::exit;
+use transport::SerioRaw;
+use vendor::Region;
+
+const USAGE: &str = "usage: ps2_client [-d DEVICE] info
+       ps2_client [-d DEVICE] read text|macros|keymap FILE
+       ps2_client [-d DEVICE] write text|macros|keymap FILE
+
+The frames of the keyboard only get through an i8042 booted with i8042.direct=1, its
+translation to scancode set 1 changes them. The port has to be bound to serio_raw:
+  echo -n serio_raw > /sys/bus/serio/devices/serio0/drvctl";
+
+fn region(name: &str) -> Option<Region> {
+    match name {
+        "text" => Some(Region::TextExpansion),
+        "macros" => Some(Region::Macros),
+        "keymap" => Some(Region::Keymap),
+        _ => None,
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    exit(1)
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut device = String::from("/dev/serio_raw0");
+    if args.first().map(|a| a.as_str()) == Some("-d") && args.len() > 1 {
+        device = args.remove(1);
+        args.remove(0);
+    }
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    let transport =
+        SerioRaw::open(&device).unwrap_or_else(|e| fail(&format!("can't open {}: {}", device, e)));
+    let mut client = Client::new(transport);
+    let info = client
+        .open()
+        .unwrap_or_else(|e| fail(&format!("no session: {}", e)));
+    let result = match args[..] {
+        ["info"] => {
+            println!(
+                "protocol version {}, {} byte payloads, {} regions",
+                info.version, info.max_payload, info.regions
+            );
+            Ok(())
+        }
+        ["read", name, file] => {
+            let region = region(name).unwrap_or_else(|| fail(USAGE));
+            client.read(region).map(|data| {
+                fs::write(file, data).unwrap_or_else(|e| fail(&format!("{}: {}", file, e)))
+            })
+        }
+        ["write", name, file] => {
+            let region = region(name).unwrap_or_else(|| fail(USAGE));
+            let data = fs::read(file).unwrap_or_else(|e| fail(&format!("{}: {}", file, e)));
+            client.write(region, &data)
+        }
+        _ => {
+            let _ = client.close();
+            fail(USAGE)
+        }
+    };
+    let _ = client.close();
+    if let Err(e) = result {
+        fail(&e.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{Client, Error};
+    use crate::simulated::SimulatedKeyboard;
+    use crate::transport::Transport;
+    use crate::vendor::{encode_frame, Command, Region, Status, RESPONSE, SYNC, UNLOCK};
+    use std::time::Duration;
+
+    fn round_trip(corrupt_every: usize) {
+        let mut client = Client::new(SimulatedKeyboard::new(corrupt_every));
+        client.open().unwrap();
+        let image: Vec<u8> = (0..1500u32).map(|i| (i * 7 + i / 13) as u8).collect();
+        for region in [Region::TextExpansion, Region::Macros, Region::Keymap] {
+            client.write(region, &image).unwrap();
+            assert_eq!(client.read(region).unwrap(), image);
+            assert_eq!(client.transport().target.regions[region as usize], image);
+        }
+        client.close().unwrap();
+    }
+
+    fn received(keyboard: &mut SimulatedKeyboard) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while let Some(byte) = keyboard.receive(Duration::ZERO).unwrap() {
+            bytes.push(byte);
+        }
+        bytes
+    }
+
+    #[test]
+    fn clean_round_trip() {
+        round_trip(0);
+    }
+
+    //every 97th byte to the keyboard gets damaged, the frames with it get sent again
+    #[test]
+    fn corrupted_round_trip() {
+        round_trip(97);
+    }
+
+    #[test]
+    fn locked_without_unlock() {
+        let mut client = Client::new(SimulatedKeyboard::new(0));
+        assert!(matches!(client.info(), Err(Error::NoResponse)));
+        let info = client.open().unwrap();
+        assert_eq!((info.max_payload, info.regions), (16, 3));
+    }
+
+    #[test]
+    fn bad_crc_answered() {
+        let mut keyboard = SimulatedKeyboard::new(0);
+        keyboard.send(&UNLOCK).unwrap();
+        assert_eq!(received(&mut keyboard), [0xFA; 4]);
+        let mut frame = Vec::new();
+        encode_frame(Command::Info as u8, &[], &mut frame);
+        *frame.last_mut().unwrap() ^= 0x10;
+        keyboard.send(&frame).unwrap();
+        let mut expected = Vec::new();
+        encode_frame(
+            Command::Info as u8 | RESPONSE,
+            &[Status::BadCrc as u8],
+            &mut expected,
+        );
+        assert_eq!(expected[0], SYNC);
+        assert_eq!(received(&mut keyboard), expected);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::crc;
+use crate::transport::Transport;
+use crate::vendor::{Received, Region, Session, VendorTarget};
+use std::io;
+use std::time::Duration;
+
+//Region images kept in memory, stands in for the keyboards storage
+pub struct MemoryTarget {
+    pub regions: [Vec<u8>; 3],
+}
+impl VendorTarget for MemoryTarget {
+    fn region_len(&self, region: Region) -> usize {
+        self.regions[region as usize].len()
+    }
+    fn read(&self, region: Region, offset: usize, buffer: &mut [u8]) -> usize {
+        let data = self.regions[region as usize].get(offset..).unwrap_or(&[]);
+        let len = data.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&data[..len]);
+        len
+    }
+    fn commit(&mut self, region: Region, data: &[u8]) -> bool {
+        self.regions[region as usize] = data.to_vec();
+        true
+    }
+}
+
+//Runs the firmwares session code in process. Every nth byte sent to the keyboard gets
+//corrupted so that the retransmissions are exercised as well
+pub struct SimulatedKeyboard {
+    session: Session<2044>,
+    pub target: MemoryTarget,
+    output: Vec<u8>,
+    time: u32,
+    corrupt_every: usize,
+    until_corrupt: usize,
+}
+
+impl SimulatedKeyboard {
+    pub fn new(corrupt_every: usize) -> Self {
+        Self {
+            session: Session::new(),
+            target: MemoryTarget {
+                regions: [Vec::new(), Vec::new(), Vec::new()],
+            },
+            output: Vec::new(),
+            time: 0,
+            corrupt_every,
+            until_corrupt: corrupt_every,
+        }
+    }
+}
+impl Transport for SimulatedKeyboard {
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        for byte in data {
+            let mut byte = *byte;
+            if self.corrupt_every != 0 {
+                self.until_corrupt -= 1;
+                if self.until_corrupt == 0 {
+                    self.until_corrupt = self.corrupt_every;
+                    byte ^= crc::crc8(&[byte]) | 1;
+                }
+            }
+            self.time += 1;
+            self.session.poll(self.time);
+            match self
+                .session
+                .receive(byte, self.time, &mut self.target, &mut self.output)
+            {
+                //a real keyboard acks every command it doesn't know
+                Received::NotVendor | Received::Unlocking => self.output.push(0xFA),
+                Received::Session => {}
+            }
+        }
+        Ok(())
+    }
+    fn receive(&mut self, _timeout: Duration) -> io::Result<Option<u8>> {
+        if self.output.is_empty() {
+            //nothing more is coming, the host waited for the whole timeout
+            self.time += 100;
+            return Ok(None);
+        }
+        Ok(Some(self.output.remove(0)))
+    }
+}
@@ -0,0 +1,58 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+pub trait Transport {
+    fn send(&mut self, data: &[u8]) -> io::Result<()>;
+    //None on timeout
+    fn receive(&mut self, timeout: Duration) -> io::Result<Option<u8>>;
+}
+
+//The keyboard port bound to the serio_raw driver, as root:
+//  echo -n serio_raw > /sys/bus/serio/devices/serio0/drvctl
+//and back to the normal driver with
+//  echo -n atkbd > /sys/bus/serio/devices/serio0/drvctl
+pub struct SerioRaw {
+    device: File,
+    bytes: Receiver<io::Result<u8>>,
+}
+
+impl SerioRaw {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let device = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut reader = device.try_clone()?;
+        let (sender, bytes) = mpsc::channel();
+        //serio_raw has no read timeout, a thread does the blocking reads
+        thread::spawn(move || {
+            let mut byte = [0u8];
+            loop {
+                let result = reader.read_exact(&mut byte).map(|_| byte[0]);
+                let failed = result.is_err();
+                if sender.send(result).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Self { device, bytes })
+    }
+}
+impl Transport for SerioRaw {
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        //serio_raw takes one byte per write on some kernels, send them separately
+        for byte in data {
+            self.device.write_all(&[*byte])?;
+        }
+        Ok(())
+    }
+    fn receive(&mut self, timeout: Duration) -> io::Result<Option<u8>> {
+        match self.bytes.recv_timeout(timeout) {
+            Ok(result) => result.map(Some),
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "reader stopped"))
+            }
+        }
+    }
+}